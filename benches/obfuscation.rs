@@ -0,0 +1,77 @@
+use kindle_tool::Kernel;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const BUFFER_SIZE: usize = 64 * 1024 * 1024;
+const MIN_DURATION: Duration = Duration::from_secs(1);
+
+// the per-byte loops the kernels replaced, kept as the reference to beat
+#[inline(always)]
+fn deobfuscate_byte(byte: u8) -> u8 {
+  byte.rotate_left(4) ^ 0xA7
+}
+
+fn baseline_deobfuscate(data: &mut [u8]) {
+  for byte in data.iter_mut() {
+    *byte = deobfuscate_byte(*byte);
+  }
+}
+
+#[inline(always)]
+fn obfuscate_byte(byte: u8) -> u8 {
+  byte.rotate_left(4) ^ 0x7A
+}
+
+fn baseline_obfuscate(data: &mut [u8]) {
+  for byte in data.iter_mut() {
+    *byte = obfuscate_byte(*byte);
+  }
+}
+
+// throughput in GiB/s of `transform` over `data`, repeated for at least MIN_DURATION
+fn measure(data: &mut [u8], transform: impl Fn(&mut [u8])) -> f64 {
+  transform(data);
+
+  let mut rounds = 0;
+  let start = Instant::now();
+  while start.elapsed() < MIN_DURATION {
+    transform(black_box(&mut *data));
+    rounds += 1;
+  }
+  let bytes = (rounds * data.len()) as f64;
+  bytes / start.elapsed().as_secs_f64() / (1024.0 * 1024.0 * 1024.0)
+}
+
+fn main() {
+  // odd length so the scalar tail of the vector kernels is exercised
+  let input: Vec<u8> = (0..BUFFER_SIZE + 7).map(|i| (i * 31 % 251) as u8).collect();
+
+  let mut deobfuscated = input.clone();
+  Kernel::Scalar.deobfuscate(&mut deobfuscated);
+  let mut obfuscated = input.clone();
+  Kernel::Scalar.obfuscate(&mut obfuscated);
+
+  let mut data = input.clone();
+  baseline_deobfuscate(&mut data);
+  assert_eq!(data, deobfuscated, "baseline deobfuscation disagrees with scalar");
+  data.copy_from_slice(&input);
+  baseline_obfuscate(&mut data);
+  assert_eq!(data, obfuscated, "baseline obfuscation disagrees with scalar");
+
+  println!("{:<8} {:>14} {:>14}", "kernel", "deobfuscate", "obfuscate");
+  let deobfuscate = measure(&mut data, baseline_deobfuscate);
+  let obfuscate = measure(&mut data, baseline_obfuscate);
+  println!("{:<8} {:>9.2} GiB/s {:>9.2} GiB/s", "baseline", deobfuscate, obfuscate);
+  for kernel in Kernel::available() {
+    let mut data = input.clone();
+    kernel.deobfuscate(&mut data);
+    assert_eq!(data, deobfuscated, "{} deobfuscation disagrees with scalar", kernel);
+    data.copy_from_slice(&input);
+    kernel.obfuscate(&mut data);
+    assert_eq!(data, obfuscated, "{} obfuscation disagrees with scalar", kernel);
+
+    let deobfuscate = measure(&mut data, |d| kernel.deobfuscate(d));
+    let obfuscate = measure(&mut data, |d| kernel.obfuscate(d));
+    println!("{:<8} {:>9.2} GiB/s {:>9.2} GiB/s", kernel, deobfuscate, obfuscate);
+  }
+}
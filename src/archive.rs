@@ -0,0 +1,375 @@
+use crate::{deobfuscate_in_place, DeobfuscatingReader, Error, UpdateBundle};
+use flate2::read::GzDecoder;
+use serde::Serialize;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::io::{BufReader, Read, Seek};
+use std::path::{Component, Path, PathBuf};
+use strum::Display as StrumDisplay;
+use tar::{Archive, EntryType};
+
+fn payload_archive<R: Read + Seek>(reader: &mut R) -> Result<Archive<impl Read + '_>, Error> {
+  let (_bundle, payload) = UpdateBundle::read_supported(reader)?;
+  Ok(Archive::new(GzDecoder::new(BufReader::new(DeobfuscatingReader::new(payload)))))
+}
+
+// the ext2/3 superblock magic sits furthest in, 56 bytes into the superblock at 1024
+const SNIFF_LEN: u64 = 1082;
+
+/// the container or compression format of a deobfuscated payload
+#[derive(Debug, Clone, Copy, PartialEq, Eq, StrumDisplay, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PayloadFormat {
+  #[strum(to_string = "gzip")]
+  Gzip,
+  #[strum(to_string = "xz")]
+  Xz,
+  #[strum(to_string = "bzip2")]
+  Bzip2,
+  #[strum(to_string = "zstd")]
+  Zstd,
+  #[strum(to_string = "tar")]
+  Tar,
+  #[strum(to_string = "ext3 image")]
+  Ext3,
+  #[strum(to_string = "u-boot uImage")]
+  UImage,
+  #[strum(to_string = "unknown")]
+  Unknown,
+}
+
+impl PayloadFormat {
+  /// identifies the format from the leading bytes of a deobfuscated payload
+  pub fn detect(data: &[u8]) -> Self {
+    match data {
+      [0x1F, 0x8B, ..] => Self::Gzip,
+      [0xFD, b'7', b'z', b'X', b'Z', 0x00, ..] => Self::Xz,
+      [b'B', b'Z', b'h', b'1'..=b'9', ..] => Self::Bzip2,
+      [0x28, 0xB5, 0x2F, 0xFD, ..] => Self::Zstd,
+      [0x27, 0x05, 0x19, 0x56, ..] => Self::UImage,
+      _ if data.get(257..262) == Some(b"ustar") => Self::Tar,
+      _ if data.get(1080..1082) == Some(&[0x53, 0xEF]) => Self::Ext3,
+      _ => Self::Unknown,
+    }
+  }
+
+  /// the file extension conventionally used for this format
+  pub fn extension(self) -> &'static str {
+    match self {
+      Self::Gzip => "tar.gz",
+      Self::Xz => "xz",
+      Self::Bzip2 => "bz2",
+      Self::Zstd => "zst",
+      Self::Tar => "tar",
+      Self::Ext3 => "img",
+      Self::UImage => "uImage",
+      Self::Unknown => "dat",
+    }
+  }
+}
+
+/// sniffs the format of the payload of the bundle read from `reader`.
+/// only the first kilobyte or so of the payload is read
+pub fn payload_format<R: Read + Seek>(reader: &mut R) -> Result<PayloadFormat, Error> {
+  let (_bundle, payload) = UpdateBundle::read_supported(reader)?;
+  let mut head = Vec::with_capacity(SNIFF_LEN as usize);
+  payload.take(SNIFF_LEN).read_to_end(&mut head)?;
+  Ok(sniff(&head))
+}
+
+// the format of a payload starting with the still obfuscated `payload` bytes
+pub(crate) fn sniff(payload: &[u8]) -> PayloadFormat {
+  let mut head = payload[..payload.len().min(SNIFF_LEN as usize)].to_vec();
+  deobfuscate_in_place(&mut head);
+  PayloadFormat::detect(&head)
+}
+
+fn is_contained(path: &Path) -> bool {
+  path.components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+}
+
+// whether the contained `path` would still land outside of the canonical `dir`
+// by going through a symlink unpacked earlier, e.g. `lnk/evil` after `lnk -> /tmp`.
+// only the parents are resolved: the entry itself replaces whatever is there
+fn escapes_through_symlink(dir: &Path, path: &Path) -> bool {
+  let mut current = dir.to_path_buf();
+  for component in path.parent().into_iter().flat_map(Path::components) {
+    current.push(component);
+    if current.symlink_metadata().is_err() {
+      // created fresh from here on
+      return false;
+    }
+    if current.canonicalize().is_ok_and(|resolved| !resolved.starts_with(dir)) {
+      return true;
+    }
+  }
+  false
+}
+
+/// unpacks the tar.gz payload of the bundle read from `reader` into `dir`,
+/// preserving modes, mtimes and symlinks. entries escaping `dir` are refused and
+/// `.sig` signature files are dropped when `skip_signatures` is set.
+/// returns the paths of the unpacked entries, relative to `dir`
+pub fn extract_payload<R: Read + Seek>(
+  reader: &mut R,
+  dir: &Path,
+  skip_signatures: bool,
+) -> Result<Vec<PathBuf>, Error> {
+  let mut archive = payload_archive(reader)?;
+  archive.set_preserve_permissions(true);
+  archive.set_preserve_mtime(true);
+  archive.set_overwrite(true);
+
+  std::fs::create_dir_all(dir)?;
+  let dir = dir.canonicalize()?;
+
+  let mut extracted = Vec::new();
+  for entry in archive.entries()? {
+    let mut entry = entry?;
+    let path = entry.path()?.into_owned();
+    if !is_contained(&path) || escapes_through_symlink(&dir, &path) {
+      return Err(Error::PathTraversal(path));
+    }
+    if entry.header().entry_type() == EntryType::Link
+      && let Some(target) = entry.link_name()?
+      && (!is_contained(&target) || escapes_through_symlink(&dir, &target))
+    {
+      return Err(Error::PathTraversal(target.into_owned()));
+    }
+    if skip_signatures && path.extension().is_some_and(|ext| ext == "sig") {
+      continue;
+    }
+
+    if entry.unpack_in(&dir)? {
+      extracted.push(path);
+    }
+  }
+
+  Ok(extracted)
+}
+
+#[derive(Debug, Clone)]
+pub struct PayloadEntry {
+  pub path: PathBuf,
+  pub entry_type: EntryType,
+  pub size: u64,
+  pub mode: u32,
+  /// seconds since the unix epoch
+  pub mtime: u64,
+  pub link_name: Option<PathBuf>,
+}
+
+impl PayloadEntry {
+  /// whether the entry is an update script (`*.ffs`, `*.sh`) rather than data
+  pub fn is_script(&self) -> bool {
+    self.entry_type.is_file() && self.path.extension().is_some_and(|ext| ext == "ffs" || ext == "sh")
+  }
+
+  /// whether the entry is a regular file other than an update script;
+  /// directories and links are neither
+  pub fn is_data(&self) -> bool {
+    self.entry_type.is_file() && !self.is_script()
+  }
+}
+
+// days since the epoch to a proleptic gregorian (year, month, day)
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+  let z = days + 719468;
+  let era = z.div_euclid(146097);
+  let doe = z.rem_euclid(146097);
+  let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+  let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+  let mp = (5 * doy + 2) / 153;
+  let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+  let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+  let year = yoe + era * 400 + i64::from(month <= 2);
+  (year, month, day)
+}
+
+impl Display for PayloadEntry {
+  fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+    let kind = match self.entry_type {
+      EntryType::Directory => 'd',
+      EntryType::Symlink => 'l',
+      EntryType::Link => 'h',
+      EntryType::Char => 'c',
+      EntryType::Block => 'b',
+      EntryType::Fifo => 'p',
+      _ => '-',
+    };
+    let secs = self.mtime as i64;
+    let (year, month, day) = civil_from_days(secs.div_euclid(86400));
+    let time = secs.rem_euclid(86400);
+
+    write!(
+      f,
+      "{}{:04o} {:>10} {:04}-{:02}-{:02} {:02}:{:02} {}",
+      kind,
+      self.mode & 0o7777,
+      self.size,
+      year,
+      month,
+      day,
+      time / 3600,
+      time % 3600 / 60,
+      self.path.display()
+    )?;
+    if let Some(target) = &self.link_name {
+      write!(f, " -> {}", target.display())?;
+    }
+    Ok(())
+  }
+}
+
+/// lists the entries of the tar.gz payload of the bundle read from `reader`
+/// without unpacking them
+pub fn list_payload<R: Read + Seek>(reader: &mut R) -> Result<Vec<PayloadEntry>, Error> {
+  let mut archive = payload_archive(reader)?;
+
+  let mut entries = Vec::new();
+  for entry in archive.entries()? {
+    let entry = entry?;
+    let header = entry.header();
+    entries.push(PayloadEntry {
+      path: entry.path()?.into_owned(),
+      entry_type: header.entry_type(),
+      size: header.size()?,
+      mode: header.mode()?,
+      mtime: header.mtime()?,
+      link_name: entry.link_name()?.map(|p| p.into_owned()),
+    });
+  }
+
+  Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{create_bundle, Device, OtaV1};
+  use flate2::write::GzEncoder;
+  use flate2::Compression;
+  use std::io::{Cursor, Write};
+
+  // `magic` at `offset` in an otherwise zeroed buffer
+  fn at(offset: usize, magic: &[u8]) -> Vec<u8> {
+    let mut data = vec![0; offset + magic.len() + 16];
+    data[offset..offset + magic.len()].copy_from_slice(magic);
+    data
+  }
+
+  #[test]
+  fn detects_payload_formats() {
+    let cases = [
+      (at(0, &[0x1F, 0x8B, 0x08]), PayloadFormat::Gzip),
+      (at(0, b"\xFD7zXZ\x00"), PayloadFormat::Xz),
+      (at(0, b"BZh9"), PayloadFormat::Bzip2),
+      (at(0, &[0x28, 0xB5, 0x2F, 0xFD]), PayloadFormat::Zstd),
+      (at(0, &[0x27, 0x05, 0x19, 0x56]), PayloadFormat::UImage),
+      (at(257, b"ustar\x0000"), PayloadFormat::Tar),
+      (at(1080, &[0x53, 0xEF]), PayloadFormat::Ext3),
+      (at(0, b"BZh0"), PayloadFormat::Unknown),
+      (at(256, b"ustar"), PayloadFormat::Unknown),
+      // cut off inside the ext3 magic
+      (at(1080, &[0x53, 0xEF])[..1081].to_vec(), PayloadFormat::Unknown),
+      (Vec::new(), PayloadFormat::Unknown),
+    ];
+    for (data, expected) in cases {
+      assert_eq!(PayloadFormat::detect(&data), expected, "{:02X?}", &data[..data.len().min(8)]);
+    }
+  }
+
+  // an empty directory under the system temp dir, unique to the test
+  fn scratch(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("kindle-tool-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+  }
+
+  // an OTA bundle whose tar.gz payload holds `entries` of (type, path, link
+  // target), written to the raw headers so that tar's own checks are bypassed
+  fn bundle(entries: &[(EntryType, &str, &str)]) -> Vec<u8> {
+    let mut builder = tar::Builder::new(Vec::new());
+    for &(kind, path, link) in entries {
+      let mut header = tar::Header::new_gnu();
+      header.as_old_mut().name[..path.len()].copy_from_slice(path.as_bytes());
+      header.as_old_mut().linkname[..link.len()].copy_from_slice(link.as_bytes());
+      header.set_entry_type(kind);
+      header.set_mode(if kind == EntryType::Directory { 0o755 } else { 0o644 });
+      let data: &[u8] = if kind == EntryType::Regular { b"data" } else { b"" };
+      header.set_size(data.len() as u64);
+      header.set_cksum();
+      builder.append(&header, data).unwrap();
+    }
+    let mut gz = GzEncoder::new(Vec::new(), Compression::fast());
+    gz.write_all(&builder.into_inner().unwrap()).unwrap();
+
+    let header = UpdateBundle::OtaV1Fc02(OtaV1::new(1, 2, Device::Kindle3WiFi, false).unwrap());
+    let mut bundle = Vec::new();
+    create_bundle(header, &mut Cursor::new(gz.finish().unwrap()), &mut bundle).unwrap();
+    bundle
+  }
+
+  fn extract(dir: &Path, entries: &[(EntryType, &str, &str)]) -> Result<Vec<PathBuf>, Error> {
+    extract_payload(&mut Cursor::new(bundle(entries)), dir, false)
+  }
+
+  #[test]
+  fn refuses_parent_and_absolute_paths() {
+    let root = scratch("escape-paths");
+    for path in ["../x", "a/../../x", "/tmp/x"] {
+      let result = extract(&root.join("out"), &[(EntryType::Regular, path, "")]);
+      assert!(matches!(result, Err(Error::PathTraversal(_))), "{}", path);
+    }
+    assert!(!root.join("x").exists());
+    std::fs::remove_dir_all(&root).unwrap();
+  }
+
+  #[test]
+  fn refuses_hard_links_out() {
+    let root = scratch("escape-links");
+    let outside = root.join("outside");
+    std::fs::write(&outside, b"secret").unwrap();
+    let result = extract(&root.join("out"), &[(EntryType::Link, "h", "../outside")]);
+    assert!(matches!(result, Err(Error::PathTraversal(_))));
+    let result = extract(&root.join("out"), &[(EntryType::Link, "h", "/etc/passwd")]);
+    assert!(matches!(result, Err(Error::PathTraversal(_))));
+    std::fs::remove_dir_all(&root).unwrap();
+  }
+
+  #[test]
+  fn refuses_entries_through_symlinks_out() {
+    let root = scratch("escape-symlinks");
+    let outside = root.join("outside");
+    std::fs::create_dir_all(&outside).unwrap();
+    std::fs::write(outside.join("secret"), b"secret").unwrap();
+    let lnk = outside.to_str().unwrap();
+
+    let result = extract(&root.join("out"), &[(EntryType::Symlink, "lnk", lnk), (EntryType::Regular, "lnk/evil", "")]);
+    assert!(matches!(result, Err(Error::PathTraversal(_))));
+    assert!(!outside.join("evil").exists());
+
+    let result = extract(&root.join("out"), &[(EntryType::Symlink, "lnk", lnk), (EntryType::Link, "h", "lnk/secret")]);
+    assert!(matches!(result, Err(Error::PathTraversal(_))));
+    std::fs::remove_dir_all(&root).unwrap();
+  }
+
+  #[test]
+  fn keeps_contained_entries() {
+    let root = scratch("contained");
+    let out = root.join("out");
+    let entries = [
+      (EntryType::Directory, "d/", ""),
+      (EntryType::Regular, "d/f", ""),
+      (EntryType::Symlink, "abs", "/usr/bin"),
+      (EntryType::Symlink, "rel", "d"),
+      (EntryType::Regular, "rel/g", ""),
+      (EntryType::Link, "h", "d/f"),
+    ];
+    let extracted = extract(&out, &entries).unwrap();
+    assert_eq!(extracted.len(), entries.len());
+    assert_eq!(std::fs::read(out.join("d/g")).unwrap(), b"data");
+    assert_eq!(std::fs::read_link(out.join("abs")).unwrap(), Path::new("/usr/bin"));
+    std::fs::remove_dir_all(&root).unwrap();
+  }
+}
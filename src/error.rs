@@ -0,0 +1,135 @@
+use crate::{printable, SignatureStatus};
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::io::{ErrorKind, Seek};
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub enum Error {
+  /// the leading four bytes are not a known bundle magic
+  UnknownMagic([u8; 4]),
+  /// the input ended inside a header; sizes are counted from the start of the bundle
+  TruncatedHeader { expected: u64, got: u64 },
+  /// a header md5 field is not 32 ascii hex characters
+  InvalidMd5Encoding(String),
+  /// a signature or key does not have the size its cert number requires
+  SignatureSizeMismatch { cert_num: u32, expected: usize, got: usize },
+  /// a header field value cannot be represented in the bundle format
+  InvalidHeader(String),
+  /// bundles with this magic cannot be created from a payload
+  UnsupportedBundle(&'static str),
+  /// the bundle is not wrapped in a signature envelope
+  NotSigned(&'static str),
+  InvalidKey(String),
+  Rsa(rsa::Error),
+  /// the envelope signature could not be checked with the supplied keys
+  BadSignature(SignatureStatus),
+  /// the payload does not hash to the md5 stored in the header
+  Md5Mismatch { expected: String, actual: String },
+  /// a payload entry would land outside of the target directory
+  PathTraversal(PathBuf),
+  /// the name, alias or code does not match a known device
+  UnknownDevice(String),
+  /// the model alias has no single representative variant
+  AmbiguousDevice(String),
+  /// the name, alias or code does not match a known platform
+  UnknownPlatform(String),
+  /// the serial number does not decode to a known device
+  UnknownSerial(String),
+  /// malformed header data not covered by the variants above
+  Parse(binrw::Error),
+  Io(std::io::Error),
+}
+
+impl Error {
+  // wraps the error for binrw, to be recovered by `From<binrw::Error>`
+  pub(crate) fn into_binrw<S: Seek>(self, stream: &mut S) -> binrw::Error {
+    binrw::Error::Custom {
+      pos: stream.stream_position().unwrap_or_default(),
+      err: Box::new(self),
+    }
+  }
+
+  pub(crate) fn is_eof(&self) -> bool {
+    matches!(self, Error::Io(e) if e.kind() == ErrorKind::UnexpectedEof)
+  }
+}
+
+impl Display for Error {
+  fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+    match self {
+      Error::UnknownMagic(magic) => write!(f, "unknown bundle magic {:02X?} ({})", magic, printable(magic)),
+      Error::TruncatedHeader { expected, got } => {
+        write!(f, "truncated header: expected at least {} bytes, got {}", expected, got)
+      }
+      Error::InvalidMd5Encoding(md5) => write!(f, "md5 hash '{}' is not 32 hex characters", md5.escape_debug()),
+      Error::SignatureSizeMismatch { cert_num, expected, got } => {
+        write!(f, "cert {} needs a {}-byte signature, got {} bytes", cert_num, expected, got)
+      }
+      Error::InvalidHeader(msg) => write!(f, "{}", msg),
+      Error::UnsupportedBundle(magic) => write!(f, "{} bundles cannot be created from a payload", magic),
+      Error::NotSigned(magic) => write!(f, "{} bundles are not signed", magic),
+      Error::InvalidKey(msg) => write!(f, "invalid key: {}", msg),
+      Error::Rsa(e) => write!(f, "rsa: {}", e),
+      Error::BadSignature(status) => write!(f, "signature is {}", status),
+      Error::Md5Mismatch { expected, actual } => {
+        write!(f, "payload md5 {} does not match header md5 {}", actual, expected)
+      }
+      Error::PathTraversal(path) => write!(f, "refusing to extract '{}' outside of the target directory", path.display()),
+      Error::UnknownDevice(name) => write!(f, "unknown device '{}'", name.escape_debug()),
+      Error::AmbiguousDevice(alias) => {
+        write!(f, "'{}' names several device variants; pick one or use all-{}", alias, alias)
+      }
+      Error::UnknownPlatform(name) => write!(f, "unknown platform '{}'", name.escape_debug()),
+      Error::UnknownSerial(serial) => write!(f, "serial number '{}' does not match a known device", serial.escape_debug()),
+      Error::Parse(e) => write!(f, "{}", e),
+      Error::Io(e) => write!(f, "{}", e),
+    }
+  }
+}
+
+impl std::error::Error for Error {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      Error::Rsa(e) => Some(e),
+      Error::Parse(e) => Some(e),
+      Error::Io(e) => Some(e),
+      _ => None,
+    }
+  }
+}
+
+impl From<std::io::Error> for Error {
+  fn from(err: std::io::Error) -> Self {
+    Error::Io(err)
+  }
+}
+
+impl From<rsa::Error> for Error {
+  fn from(err: rsa::Error) -> Self {
+    Error::Rsa(err)
+  }
+}
+
+impl From<binrw::Error> for Error {
+  fn from(err: binrw::Error) -> Self {
+    match err {
+      binrw::Error::Io(e) => Error::Io(e),
+      binrw::Error::Custom { pos, err } => match err.downcast::<Error>() {
+        Ok(e) => *e,
+        Err(err) => Error::Parse(binrw::Error::Custom { pos, err }),
+      },
+      binrw::Error::Backtrace(bt) => Error::from(*bt.error),
+      // the variant whose magic matched carries the actual failure
+      binrw::Error::EnumErrors { pos, variant_errors } => {
+        match variant_errors
+          .into_iter()
+          .find(|(_, e)| !matches!(e.root_cause(), binrw::Error::BadMagic { .. }))
+        {
+          Some((_, e)) => Error::from(e),
+          None => Error::Parse(binrw::Error::NoVariantMatch { pos }),
+        }
+      }
+      e => Error::Parse(e),
+    }
+  }
+}
@@ -1,46 +1,156 @@
-use binrw::{BinRead, BinReaderExt, BinResult, Endian};
+use binrw::io::NoSeek;
+use binrw::{binrw, BinRead, BinReaderExt, BinResult, BinWrite, BinWriterExt, Endian};
+use md5::{Digest, Md5};
+use serde::ser::{SerializeMap, SerializeStruct};
+use serde::{Serialize, Serializer};
 use std::fmt::{Display, Formatter, Result as FmtResult};
 use std::io::{Cursor, Read, Seek, SeekFrom, Write};
-use strum::{Display as StrumDisplay, FromRepr};
+use strum::{Display as StrumDisplay, EnumIter, FromRepr, IntoEnumIterator};
 
-// a LUT would be ~7x slower (benchmarked on aarch64)
-#[inline(always)]
-fn deobfuscate_byte(byte: u8) -> u8 {
-  ((byte >> 4) | (byte << 4)) ^ 0xA7
-}
-
-pub fn deobfuscate_in_place(data: &mut [u8]) {
-  for byte in data.iter_mut() {
-    *byte = deobfuscate_byte(*byte);
-  }
-}
+mod archive;
+mod error;
+mod obfuscation;
+mod payload;
+mod signing;
+mod targets;
+mod view;
+pub use archive::{extract_payload, list_payload, payload_format, PayloadEntry, PayloadFormat};
+pub use error::Error;
+pub use obfuscation::{
+  deobfuscate_in_place, deobfuscate_stream, obfuscate_in_place, obfuscate_stream, DeobfuscatingReader, Kernel,
+  ObfuscatingWriter, CHUNK_SIZE,
+};
+pub use payload::PayloadReader;
+pub use signing::{
+  bundle_signature, parse_private_key, parse_public_key, sign_bundle, unwrap_bundle, verify_signature,
+  write_signed_bundle, EnvelopeSignature, SignatureStatus,
+};
+pub use targets::parse_code;
+pub use view::BundleView;
 
-#[inline(always)]
-fn obfuscate_byte(byte: u8) -> u8 {
-  ((byte >> 4) | (byte << 4)) ^ 0x7A
+fn is_md5_encoding(md5_hash: &[u8]) -> bool {
+  md5_hash.len() == 32 && md5_hash.iter().all(u8::is_ascii_hexdigit)
 }
 
-pub fn obfuscate_in_place(data: &mut [u8]) {
-  for byte in data.iter_mut() {
-    *byte = obfuscate_byte(*byte);
-  }
+// bundle headers are measured from their magic, which the enum reads before
+// handing over at `start`, to where parsing them stopped
+fn header_len_from<R: Seek>(reader: &mut R, start: u64) -> BinResult<Option<u64>> {
+  Ok(Some(4 + reader.stream_position()? - start))
 }
 
 mod parsers {
-  use super::{deobfuscate_in_place, BinResult};
+  use super::{deobfuscate_in_place, is_md5_encoding, BinResult, Error};
+  use std::io::Read;
 
   #[binrw::parser(reader)]
   pub fn parse_deobfuscated_md5() -> BinResult<String> {
     let mut buf = [0u8; 32];
     reader.read_exact(&mut buf)?;
     deobfuscate_in_place(&mut buf);
+    if !is_md5_encoding(&buf) {
+      reader.seek_relative(-32)?;
+      return Err(Error::InvalidMd5Encoding(String::from_utf8_lossy(&buf).to_string()).into_binrw(reader));
+    }
     Ok(String::from_utf8_lossy(&buf).to_string())
   }
+
+  #[binrw::parser(reader)]
+  pub fn position() -> BinResult<u64> {
+    Ok(reader.stream_position()?)
+  }
+
+  #[binrw::parser(reader)]
+  pub fn header_len(start: u64) -> BinResult<Option<u64>> {
+    super::header_len_from(reader, start)
+  }
+
+  #[binrw::parser(reader)]
+  pub fn parse_up_to(limit: u64) -> BinResult<Vec<u8>> {
+    let mut buf = Vec::new();
+    reader.take(limit).read_to_end(&mut buf)?;
+    Ok(buf)
+  }
+}
+
+mod writers {
+  use super::{is_md5_encoding, obfuscate_in_place, BinResult, Error};
+
+  #[binrw::writer(writer)]
+  pub fn write_obfuscated_md5(md5_hash: &String) -> BinResult<()> {
+    if !is_md5_encoding(md5_hash.as_bytes()) {
+      return Err(Error::InvalidMd5Encoding(md5_hash.clone()).into_binrw(writer));
+    }
+    let mut buf = [0u8; 32];
+    buf.copy_from_slice(md5_hash.as_bytes());
+    obfuscate_in_place(&mut buf);
+    writer.write_all(&buf)?;
+    Ok(())
+  }
+}
+
+mod serializers {
+  use super::{Device, Platform};
+  use serde::{Serialize, Serializer};
+
+  // raw code alongside its resolved name
+  #[derive(Serialize)]
+  pub struct Coded {
+    code: u32,
+    name: String,
+  }
+
+  pub fn device(code: u16) -> Coded {
+    Coded {
+      code: code.into(),
+      name: Device::from_repr(code as usize).unwrap_or_default().to_string(),
+    }
+  }
+
+  pub fn platform(code: u32) -> Coded {
+    Coded {
+      code,
+      name: Platform::from_repr(code as usize).unwrap_or_default().to_string(),
+    }
+  }
+
+  pub fn serialize_device<S: Serializer>(code: &u16, serializer: S) -> Result<S::Ok, S::Error> {
+    device(*code).serialize(serializer)
+  }
+
+  pub fn serialize_devices<S: Serializer>(codes: &[u16], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(codes.iter().map(|&code| device(code)))
+  }
+
+  pub fn serialize_platform<S: Serializer>(code: &u32, serializer: S) -> Result<S::Ok, S::Error> {
+    platform(*code).serialize(serializer)
+  }
+
+  pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+  }
+
+  #[derive(Serialize)]
+  pub struct UnknownHeader {
+    magic_bytes: String,
+    header_bytes: String,
+    neighbours: Vec<&'static str>,
+  }
+
+  pub fn unknown(magic: &[u8; 4], header: &[u8]) -> UnknownHeader {
+    UnknownHeader {
+      magic_bytes: hex(magic),
+      header_bytes: hex(header),
+      neighbours: super::UpdateBundle::neighbours(magic),
+    }
+  }
 }
 
+// base-32 digits of the device id in post-paperwhite 2 serials (no I, O, Y or Z)
+const SERIAL_ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKLMNPQRSTUVWX";
+
 // device table as of abff364 kindletool
 #[allow(non_camel_case_types)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq, BinRead, FromRepr, StrumDisplay, Default)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, BinRead, FromRepr, StrumDisplay, EnumIter, Default)]
 #[br(repr = u16)]
 pub enum Device {
   #[strum(to_string = "Kindle 1")]
@@ -415,7 +525,277 @@ pub enum Device {
   Unknown = 0,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, BinRead, FromRepr, StrumDisplay, Default)]
+impl Device {
+  /// legacy devices that predate OTA V2 and only accept OTA V1 (FC02/FD03) bundles
+  pub fn is_ota_v1(self) -> bool {
+    matches!(
+      self,
+      Device::Kindle1
+        | Device::Kindle2US
+        | Device::Kindle2International
+        | Device::KindleDXUS
+        | Device::KindleDXInternational
+        | Device::Kindle3WiFi3G
+        | Device::ValidKindleUnknown_0x07
+        | Device::Kindle3WiFi
+        | Device::KindleDXGraphite
+        | Device::Kindle3WiFi3GEurope
+        | Device::ValidKindleUnknown_0x0B
+        | Device::ValidKindleUnknown_0x0C
+        | Device::ValidKindleUnknown_0x0D
+    )
+  }
+
+  /// every device in the table, in table order
+  pub fn known() -> impl Iterator<Item = Device> {
+    Device::iter().filter(|&d| d != Device::Unknown)
+  }
+
+  /// the device id as it appears in the serial number: two hex digits for
+  /// devices up to the paperwhite 2, three base-32 characters after that
+  pub fn serial_prefix(self) -> String {
+    let code = self as u16;
+    if code <= 0xFF {
+      format!("{:02X}", code)
+    } else {
+      [10, 5, 0].iter().map(|shift| SERIAL_ALPHABET[(code >> shift) as usize & 0x1F] as char).collect()
+    }
+  }
+
+  /// looks up the device a serial number belongs to. serials starting with
+  /// `B0` or `90` carry the id as two hex digits after that prefix, later ones
+  /// as three base-32 characters at offset 3
+  pub fn from_serial(serial: &str) -> Option<Device> {
+    let serial = normalize_serial(serial).into_bytes();
+    if !serial.iter().all(u8::is_ascii_alphanumeric) {
+      return None;
+    }
+
+    let code = if serial.starts_with(b"B0") || serial.starts_with(b"90") {
+      u16::from_str_radix(std::str::from_utf8(serial.get(2..4)?).ok()?, 16).ok()?
+    } else {
+      serial.get(3..6)?.iter().try_fold(0u16, |code, &c| {
+        let digit = SERIAL_ALPHABET.iter().position(|&a| a == c)?;
+        Some(code << 5 | digit as u16)
+      })?
+    };
+    Device::from_repr(code as usize).filter(|&d| d != Device::Unknown)
+  }
+
+  /// the platform the device is built on, if known
+  pub fn platform(self) -> Option<Platform> {
+    match self {
+      Device::Kindle2US
+      | Device::Kindle2International
+      | Device::KindleDXUS
+      | Device::KindleDXInternational => Some(Platform::MarioDeprecated),
+      Device::Kindle3WiFi3G
+      | Device::Kindle3WiFi
+      | Device::KindleDXGraphite
+      | Device::Kindle3WiFi3GEurope => Some(Platform::Luigi),
+      Device::Kindle4NonTouch
+      | Device::Kindle5TouchWiFi3G
+      | Device::Kindle5TouchWiFi3GEurope
+      | Device::Kindle5TouchWiFi
+      | Device::Kindle5TouchUnknown
+      | Device::Kindle4NonTouchBlack => Some(Platform::Yoshi),
+      Device::KindlePaperWhiteWiFi3G
+      | Device::KindlePaperWhiteWiFi3GCanada
+      | Device::KindlePaperWhiteWiFi3GEurope
+      | Device::KindlePaperWhiteWiFi3GJapan
+      | Device::KindlePaperWhiteWiFi3GBrazil
+      | Device::KindlePaperWhiteWiFi => Some(Platform::Yoshime),
+      Device::KindlePaperWhite2WiFiJapan
+      | Device::KindlePaperWhite2WiFi
+      | Device::KindlePaperWhite2WiFi3G
+      | Device::KindlePaperWhite2WiFi3GCanada
+      | Device::KindlePaperWhite2WiFi3GEurope
+      | Device::KindlePaperWhite2WiFi3GRussia
+      | Device::KindlePaperWhite2WiFi3GJapan
+      | Device::KindlePaperWhite2WiFi4GBInternational
+      | Device::KindlePaperWhite2WiFi3G4GBCanada
+      | Device::KindlePaperWhite2WiFi3G4GBEurope
+      | Device::KindlePaperWhite2WiFi3G4GBBrazil
+      | Device::KindlePaperWhite2WiFi3G4GB
+      | Device::KindlePaperWhite2Unknown_0xF4
+      | Device::KindlePaperWhite2Unknown_0xF9
+      | Device::KindleVoyageWiFi
+      | Device::KindleVoyageWiFi3G
+      | Device::KindleVoyageWiFi3GJapan
+      | Device::KindleVoyageWiFi3G_0x4F
+      | Device::KindleVoyageWiFi3GMexico
+      | Device::KindleVoyageWiFi3GEurope
+      | Device::KindleBasic
+      | Device::KindleBasicKiwi => Some(Platform::Wario),
+      Device::KindlePaperWhite3WiFi
+      | Device::KindlePaperWhite3WiFi3G
+      | Device::KindlePaperWhite3WiFi3GMexico
+      | Device::KindlePaperWhite3WiFi3GEurope
+      | Device::KindlePaperWhite3WiFi3GCanada
+      | Device::KindlePaperWhite3WiFi3GJapan
+      | Device::KindlePaperWhite3WhiteWiFi
+      | Device::KindlePaperWhite3WhiteWiFi3GJapan
+      | Device::KindlePW3WhiteUnknown_0KD
+      | Device::KindlePaperWhite3WhiteWiFi3GInternational
+      | Device::KindlePaperWhite3WhiteWiFi3GInternationalBis
+      | Device::KindlePW3WhiteUnknown_0KG
+      | Device::KindlePaperWhite3BlackWiFi32GBJapan
+      | Device::KindlePaperWhite3WhiteWiFi32GBJapan
+      | Device::KindlePW3Unknown_TTT
+      | Device::KindleOasisWiFi
+      | Device::KindleOasisWiFi3G
+      | Device::KindleOasisWiFi3GInternational
+      | Device::KindleOasisUnknown_0GS
+      | Device::KindleOasisWiFi3GChina
+      | Device::KindleOasisWiFi3GEurope => Some(Platform::Duet),
+      Device::KindleBasic2Unknown_0DU
+      | Device::KindleBasic2
+      | Device::KindleBasic2White => Some(Platform::Heisenberg),
+      Device::KindleOasis2Unknown_0LM
+      | Device::KindleOasis2Unknown_0LN
+      | Device::KindleOasis2Unknown_0LP
+      | Device::KindleOasis2Unknown_0LQ
+      | Device::KindleOasis2WiFi32GBChampagne
+      | Device::KindleOasis2Unknown_0P2
+      | Device::KindleOasis2Unknown_0P6
+      | Device::KindleOasis2Unknown_0P7
+      | Device::KindleOasis2WiFi8GB
+      | Device::KindleOasis2WiFi3G32GB
+      | Device::KindleOasis2WiFi3G32GBEurope
+      | Device::KindleOasis2Unknown_0S3
+      | Device::KindleOasis2Unknown_0S4
+      | Device::KindleOasis2Unknown_0S7
+      | Device::KindleOasis2WiFi32GB
+      | Device::KindleOasis3WiFi32GBChampagne
+      | Device::KindleOasis3WiFi4G32GBJapan
+      | Device::KindleOasis3WiFi4G32GBIndia
+      | Device::KindleOasis3WiFi4G32GB
+      | Device::KindleOasis3WiFi32GB
+      | Device::KindleOasis3WiFi8GB => Some(Platform::Zelda),
+      Device::KindlePaperWhite4WiFi8GB
+      | Device::KindlePaperWhite4WiFi4G32GB
+      | Device::KindlePaperWhite4WiFi4G32GBEurope
+      | Device::KindlePaperWhite4WiFi4G32GBJapan
+      | Device::KindlePaperWhite4Unknown_0T4
+      | Device::KindlePaperWhite4Unknown_0T5
+      | Device::KindlePaperWhite4WiFi32GB
+      | Device::KindlePaperWhite4Unknown_0T7
+      | Device::KindlePaperWhite4Unknown_0TJ
+      | Device::KindlePaperWhite4Unknown_0TK
+      | Device::KindlePaperWhite4Unknown_0TL
+      | Device::KindlePaperWhite4Unknown_0TM
+      | Device::KindlePaperWhite4Unknown_0TN
+      | Device::KindlePaperWhite4WiFi8GBIndia
+      | Device::KindlePaperWhite4WiFi32GBIndia
+      | Device::KindlePaperWhite4WiFi32GBBlue
+      | Device::KindlePaperWhite4WiFi32GBPlum
+      | Device::KindlePaperWhite4WiFi32GBSage
+      | Device::KindlePaperWhite4WiFi8GBBlue
+      | Device::KindlePaperWhite4WiFi8GBPlum
+      | Device::KindlePaperWhite4WiFi8GBSage
+      | Device::KindlePW4Unknown_0PL
+      | Device::KindleBasic3
+      | Device::KindleBasic3White8GB
+      | Device::KindleBasic3Unknown_0WG
+      | Device::KindleBasic3White
+      | Device::KindleBasic3Unknown_0WJ
+      | Device::KindleBasic3KidsEdition => Some(Platform::Rex),
+      Device::KindlePaperWhite5SignatureEdition
+      | Device::KindlePaperWhite5Unknown_1Q0
+      | Device::KindlePaperWhite5
+      | Device::KindlePaperWhite5Unknown_1VD
+      | Device::KindlePaperWhite5SE_219
+      | Device::KindlePaperWhite5_21A
+      | Device::KindlePaperWhite5SE_2BH
+      | Device::KindlePaperWhite5Unknown_2BJ
+      | Device::KindlePaperWhite5_2DK
+      | Device::KindleBasic4Unknown_22D
+      | Device::KindleBasic4Unknown_25T
+      | Device::KindleBasic4Unknown_23A
+      | Device::KindleBasic4_2AQ
+      | Device::KindleBasic4_2AP
+      | Device::KindleBasic4Unknown_1XH
+      | Device::KindleBasic4Unknown_22C => Some(Platform::Bellatrix),
+      Device::KindleScribeUnknown_27J
+      | Device::KindleScribeUnknown_2BL
+      | Device::KindleScribeUnknown_263
+      | Device::KindleScribe16GB_227
+      | Device::KindleScribeUnknown_2BM
+      | Device::KindleScribe_23L
+      | Device::KindleScribe64GB_23M
+      | Device::KindleScribeUnknown_270 => Some(Platform::Bellatrix3),
+      Device::KindleBasic5Unknown_3L5
+      | Device::KindleBasic5Unknown_3L6
+      | Device::KindleBasic5Unknown_3L4
+      | Device::KindleBasic5Unknown_3L3
+      | Device::KindleBasic5Unknown_A89
+      | Device::KindleBasic5Unknown_3L2
+      | Device::KindleBasic5Unknown_3KM
+      | Device::KindlePaperWhite6Unknown_349
+      | Device::KindlePaperWhite6Unknown_346
+      | Device::KindlePaperWhite6Unknown_33X
+      | Device::KindlePaperWhite6Unknown_33W
+      | Device::KindlePaperWhite6Unknown_3HA
+      | Device::KindlePaperWhite6Unknown_3H5
+      | Device::KindlePaperWhite6Unknown_3H3
+      | Device::KindlePaperWhite6Unknown_3H8
+      | Device::KindlePaperWhite6Unknown_3J5
+      | Device::KindlePaperWhite6Unknown_3JS
+      | Device::KindleScribe2Unknown_3V0
+      | Device::KindleScribe2Unknown_3V1
+      | Device::KindleScribe2Unknown_3X5
+      | Device::KindleScribe2Unknown_3UV
+      | Device::KindleScribe2Unknown_3X4
+      | Device::KindleScribe2Unknown_3X3
+      | Device::KindleScribe2Unknown_41E
+      | Device::KindleScribe2Unknown_41D
+      | Device::KindleColorSoftUnknown_3H9
+      | Device::KindleColorSoftUnknown_3H4
+      | Device::KindleColorSoftUnknown_3HB
+      | Device::KindleColorSoftUnknown_3H6
+      | Device::KindleColorSoftUnknown_3H2
+      | Device::KindleColorSoftUnknown_34X
+      | Device::KindleColorSoftUnknown_3H7
+      | Device::KindleColorSoftUnknown_3JT
+      | Device::KindleColorSoftUnknown_3J6
+      | Device::KindleColorSoftUnknown_456
+      | Device::KindleColorSoftUnknown_455
+      | Device::KindleColorSoftUnknown_4EP => Some(Platform::Bellatrix4),
+      _ => None,
+    }
+  }
+}
+
+// serials are printed in groups of four and sometimes typed in lowercase
+fn normalize_serial(serial: &str) -> String {
+  serial.chars().filter(|c| !c.is_ascii_whitespace()).map(|c| c.to_ascii_uppercase()).collect()
+}
+
+/// how the factory root password is derived from the serial number
+#[derive(Debug, Clone, Copy, PartialEq, Eq, StrumDisplay)]
+pub enum PasswordScheme {
+  /// firmware before 5.x: `fiona` and four hex digits of the serial md5
+  #[strum(to_string = "legacy")]
+  Legacy,
+  /// firmware 5.x and later: `fiona` and three hex digits of the serial md5
+  #[strum(to_string = "5.x")]
+  Fw5,
+}
+
+/// the default root password of the device with this serial number, derived
+/// from the md5 of the serial as `echo <serial> | md5sum` computes it
+pub fn root_password(serial: &str, scheme: PasswordScheme) -> String {
+  let mut serial = normalize_serial(serial);
+  serial.push('\n');
+  let md5: String = Md5::digest(serial.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect();
+  let digits = match scheme {
+    PasswordScheme::Legacy => &md5[7..11],
+    PasswordScheme::Fw5 => &md5[13..16],
+  };
+  format!("fiona{}", digits)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, BinRead, FromRepr, StrumDisplay, EnumIter, Default)]
 #[br(repr = u32)]
 pub enum Platform {
   #[strum(to_string = "Unspecified")]
@@ -452,21 +832,59 @@ pub enum Platform {
   Unknown,
 }
 
-#[derive(Debug, BinRead)]
+#[binrw]
+#[derive(Debug, Serialize)]
 pub struct OtaV1 {
+  #[br(temp, parse_with = parsers::position)]
+  #[bw(ignore)]
+  start: u64,
   #[br(parse_with = parsers::parse_deobfuscated_md5)]
+  #[bw(write_with = writers::write_obfuscated_md5)]
   pub md5_hash: String,
   pub source_rev: u32,
   pub target_rev: u32,
+  #[serde(rename = "device", serialize_with = "serializers::serialize_device")]
   pub device_code: u16,
   pub optional: u8,
   pub padding: u8,
+  #[br(parse_with = parsers::header_len, args(start))]
+  #[bw(ignore)]
+  #[serde(skip)]
+  header_len: Option<u64>,
+}
+
+impl OtaV1 {
+  /// builds a header for a legacy `device`; the md5 hash is filled in by [`create_bundle`]
+  pub fn new(
+    source_rev: u64,
+    target_rev: u64,
+    device: Device,
+    optional: bool,
+  ) -> Result<Self, Error> {
+    if !device.is_ota_v1() {
+      return Err(Error::InvalidHeader(format!("{} (0x{:04X}) does not accept OTA V1 bundles", device, device as u16)));
+    }
+    let source_rev = u32::try_from(source_rev)
+      .map_err(|_| Error::InvalidHeader(format!("source revision {} does not fit in 32 bits", source_rev)))?;
+    let target_rev = u32::try_from(target_rev)
+      .map_err(|_| Error::InvalidHeader(format!("target revision {} does not fit in 32 bits", target_rev)))?;
+
+    Ok(Self {
+      md5_hash: String::new(),
+      source_rev,
+      target_rev,
+      device_code: device as u16,
+      optional: optional as u8,
+      padding: 0,
+      header_len: None,
+    })
+  }
 }
 
 impl Display for OtaV1 {
   fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
     let device = Device::from_repr(self.device_code as usize).unwrap_or_default();
-    writeln!(f, "{:<14} {}", "Bundle Type:", "OTA V1")?;
+    writeln!(f, "{:<14} OTA V1", "Bundle Type:")?;
     writeln!(f, "{:<14} {}", "MD5 Hash:", self.md5_hash)?;
     writeln!(f, "{:<14} {}", "Minimum OTA:", self.source_rev)?;
     writeln!(f, "{:<14} {}", "Target OTA:", self.target_rev)?;
@@ -476,8 +894,16 @@ impl Display for OtaV1 {
   }
 }
 
+/// a deobfuscated metadata string, kept as raw bytes since nothing guarantees
+/// they are utf-8; displayed and serialized lossily
 #[derive(Debug)]
-pub struct MetaString(pub String);
+pub struct MetaString(pub Vec<u8>);
+
+impl Serialize for MetaString {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&String::from_utf8_lossy(&self.0))
+  }
+}
 
 impl BinRead for MetaString {
   type Args<'a> = ();
@@ -486,35 +912,96 @@ impl BinRead for MetaString {
     let mut buf = vec![0; len as usize];
     reader.read_exact(&mut buf)?;
     deobfuscate_in_place(&mut buf);
-    Ok(MetaString(String::from_utf8_lossy(&buf).to_string()))
+    Ok(MetaString(buf))
+  }
+}
+
+impl BinWrite for MetaString {
+  type Args<'a> = ();
+  fn write_options<W: Write + Seek>(&self, writer: &mut W, _endian: Endian, _args: Self::Args<'_>) -> BinResult<()> {
+    let Ok(len) = u16::try_from(self.0.len()) else {
+      return Err(Error::InvalidHeader(format!("metadata string is too long ({} bytes)", self.0.len())).into_binrw(writer));
+    };
+    writer.write_be(&len)?;
+    let mut buf = self.0.clone();
+    obfuscate_in_place(&mut buf);
+    writer.write_all(&buf)?;
+    Ok(())
   }
 }
 
 impl Display for MetaString {
   fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
-    write!(f, "{}", self.0)
+    write!(f, "{}", String::from_utf8_lossy(&self.0))
   }
 }
 
-#[derive(Debug, BinRead)]
+// the counts are taken from the lists when writing, so edited headers stay consistent
+#[binrw]
+#[derive(Debug, Serialize)]
 pub struct OtaV2 {
+  #[br(temp, parse_with = parsers::position)]
+  #[bw(ignore)]
+  start: u64,
   pub source_rev: u64,
   pub target_rev: u64,
-  pub num_devices: u16,
+  #[br(temp)]
+  #[bw(try_calc = u16::try_from(device_codes.len()))]
+  num_devices: u16,
   #[br(count = num_devices)]
+  #[serde(rename = "devices", serialize_with = "serializers::serialize_devices")]
   pub device_codes: Vec<u16>,
   pub critical: u8,
   pub padding: u8,
   #[br(parse_with = parsers::parse_deobfuscated_md5)]
+  #[bw(write_with = writers::write_obfuscated_md5)]
   pub md5_hash: String,
-  pub num_metadata: u16,
+  #[br(temp)]
+  #[bw(try_calc = u16::try_from(metadata.len()))]
+  num_metadata: u16,
   #[br(count = num_metadata)]
   pub metadata: Vec<MetaString>,
+  #[br(parse_with = parsers::header_len, args(start))]
+  #[bw(ignore)]
+  #[serde(skip)]
+  header_len: Option<u64>,
+}
+
+impl OtaV2 {
+  /// builds a header for `devices`; the md5 hash is filled in by [`create_bundle`]
+  pub fn new(
+    source_rev: u64,
+    target_rev: u64,
+    devices: &[Device],
+    critical: bool,
+    metadata: Vec<String>,
+  ) -> Result<Self, Error> {
+    if devices.len() > u16::MAX as usize {
+      return Err(Error::InvalidHeader(format!("too many devices ({})", devices.len())));
+    }
+    if metadata.len() > u16::MAX as usize {
+      return Err(Error::InvalidHeader(format!("too many metadata strings ({})", metadata.len())));
+    }
+    if let Some(meta) = metadata.iter().find(|m| m.len() > u16::MAX as usize) {
+      return Err(Error::InvalidHeader(format!("metadata string is too long ({} bytes)", meta.len())));
+    }
+
+    Ok(Self {
+      source_rev,
+      target_rev,
+      device_codes: devices.iter().map(|&d| d as u16).collect(),
+      critical: critical as u8,
+      padding: 0,
+      md5_hash: String::new(),
+      metadata: metadata.into_iter().map(|m| MetaString(m.into_bytes())).collect(),
+      header_len: None,
+    })
+  }
 }
 
 impl Display for OtaV2 {
   fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
-    writeln!(f, "{:<14} {}", "Bundle Type:", "OTA V2")?;
+    writeln!(f, "{:<14} OTA V2", "Bundle Type:")?;
     writeln!(f, "{:<14} {}", "Minimum OTA:", self.source_rev)?;
     writeln!(f, "{:<14} {}", "Target OTA:", self.target_rev)?;
     writeln!(f, "{:<14} {}", "Critical:", self.critical)?;
@@ -533,41 +1020,101 @@ impl Display for OtaV2 {
   }
 }
 
-#[derive(Debug, BinRead)]
-#[br(little)]
+// recovery headers occupy a fixed block after the magic
+pub(crate) const RECOVERY_HEADER_SIZE: usize = 131068;
+
+// the fields sit at the start of the block, so they are read in place and
+// only the last byte of the block is probed to catch truncated files
+fn skip_recovery_block<R: Read + Seek>(reader: &mut R, start: u64) -> BinResult<()> {
+  reader.seek(SeekFrom::Start(start + RECOVERY_HEADER_SIZE as u64 - 1))?;
+  reader.read_exact(&mut [0])?;
+  Ok(())
+}
+
+// writes the header fields and fills the rest of the block from `source`, the
+// block of the header they were parsed from, so that writing a parsed header
+// back reproduces it byte for byte; without a source the rest is zeroed
+fn write_recovery_block<W: Write>(writer: &mut W, fields: &[u8], source: &[u8]) -> std::io::Result<()> {
+  let kept = source.get(fields.len()..RECOVERY_HEADER_SIZE).unwrap_or_default();
+  writer.write_all(fields)?;
+  writer.write_all(kept)?;
+  writer.write_all(&vec![0; RECOVERY_HEADER_SIZE - fields.len() - kept.len()])
+}
+
+#[derive(Debug, BinRead, BinWrite)]
+#[brw(little)]
 struct RecoveryV1Header {
-  #[br(seek_before = SeekFrom::Start(4))]
+  // offset 0
+  padding: [u8; 4],
+
+  // offset 4
   target_ota_rev2: u64,
 
-  #[br(seek_before = SeekFrom::Start(12), parse_with = parsers::parse_deobfuscated_md5)]
+  // offset 12
+  #[br(parse_with = parsers::parse_deobfuscated_md5)]
+  #[bw(write_with = writers::write_obfuscated_md5)]
   md5_hash: String,
 
   magic1: u32,
   magic2: u32,
   minor: u32,
 
-  #[br(seek_before = SeekFrom::Start(56))]
+  // offset 56
   device_or_platform_code: u32,
 
   header_rev: u32,
   board_code_rev2: u32,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct RecoveryV1 {
   pub md5_hash: String,
   pub magic1: u32,
   pub magic2: u32,
   pub minor: u32,
   pub header_rev: u32,
+  #[serde(flatten)]
   pub device_info: RecoveryDevice,
   pub target_ota: Option<u64>,
+  #[serde(skip)]
+  unused: RecoveryV1Unused,
+  #[serde(skip)]
+  header_len: Option<u64>,
+}
+
+// header bytes the fields above don't carry, kept as read so that writing a
+// parsed header back reproduces it: the target ota and board only mean
+// something in header rev 2, and device codes only use the low 16 bits
+#[derive(Debug, Default)]
+struct RecoveryV1Unused {
+  padding: [u8; 4],
+  target_ota: u64,
+  board: u32,
+  code_high: u16,
 }
 
 #[derive(Debug)]
 pub enum RecoveryDevice {
   Device(Device, u16),
-  Platform { platform: Platform, board: u32 },
+  Platform { platform: Platform, platform_code: u32, board: u32 },
+}
+
+impl Serialize for RecoveryDevice {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    match self {
+      RecoveryDevice::Device(_, code) => {
+        let mut map = serializer.serialize_map(Some(1))?;
+        map.serialize_entry("device", &serializers::device(*code))?;
+        map.end()
+      }
+      RecoveryDevice::Platform { platform_code, board, .. } => {
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry("platform", &serializers::platform(*platform_code))?;
+        map.serialize_entry("board", board)?;
+        map.end()
+      }
+    }
+  }
 }
 
 impl Display for RecoveryDevice {
@@ -576,7 +1123,7 @@ impl Display for RecoveryDevice {
       RecoveryDevice::Device(device, code) => {
         writeln!(f, "{:<14} {} (0x{:04X})", "Device:", device, code)
       }
-      RecoveryDevice::Platform { platform, board } => {
+      RecoveryDevice::Platform { platform, board, .. } => {
         writeln!(f, "{:<14} {}", "Platform:", platform)?;
         write!(f, "{:<14} Unknown (0x{:02X})", "Board:", board)
       }
@@ -587,15 +1134,15 @@ impl Display for RecoveryDevice {
 impl BinRead for RecoveryV1 {
   type Args<'a> = ();
   fn read_options<R: Read + Seek>(reader: &mut R, _endian: Endian, _args: Self::Args<'_>) -> BinResult<Self> {
-    let mut header_data = vec![0; 131068];
-    reader.read_exact(&mut header_data)?;
-    let mut cursor = Cursor::new(&header_data);
-    let header: RecoveryV1Header = cursor.read_le()?;
+    let start = reader.stream_position()?;
+    let header: RecoveryV1Header = reader.read_le()?;
+    skip_recovery_block(reader, start)?;
 
     let device_info = if header.header_rev == 2 {
       RecoveryDevice::Platform {
         platform: Platform::from_repr(header.device_or_platform_code as usize)
           .unwrap_or_default(),
+        platform_code: header.device_or_platform_code,
         board: header.board_code_rev2,
       }
     } else {
@@ -620,13 +1167,88 @@ impl BinRead for RecoveryV1 {
       header_rev: header.header_rev,
       device_info,
       target_ota,
+      unused: RecoveryV1Unused {
+        padding: header.padding,
+        target_ota: header.target_ota_rev2,
+        board: header.board_code_rev2,
+        code_high: (header.device_or_platform_code >> 16) as u16,
+      },
+      header_len: header_len_from(reader, start)?,
+    })
+  }
+}
+
+// the args are the block of the header this one was parsed from, if any
+impl BinWrite for RecoveryV1 {
+  type Args<'a> = &'a [u8];
+  fn write_options<W: Write + Seek>(&self, writer: &mut W, _endian: Endian, source: Self::Args<'_>) -> BinResult<()> {
+    let unused = &self.unused;
+    let (device_or_platform_code, board_code_rev2) = match self.device_info {
+      RecoveryDevice::Device(_, code) => ((unused.code_high as u32) << 16 | code as u32, unused.board),
+      RecoveryDevice::Platform { platform_code, board, .. } => (platform_code, board),
+    };
+
+    let header = RecoveryV1Header {
+      padding: unused.padding,
+      target_ota_rev2: self.target_ota.unwrap_or(unused.target_ota),
+      md5_hash: self.md5_hash.clone(),
+      magic1: self.magic1,
+      magic2: self.magic2,
+      minor: self.minor,
+      device_or_platform_code,
+      header_rev: self.header_rev,
+      board_code_rev2,
+    };
+
+    let mut fields = Cursor::new(Vec::new());
+    fields.write_le(&header)?;
+    write_recovery_block(writer, fields.get_ref(), source)?;
+    Ok(())
+  }
+}
+
+impl RecoveryV1 {
+  /// builds a header for `device_info`; the md5 hash is filled in by [`create_bundle`].
+  /// device targets produce header rev 1, platform targets produce header rev 2
+  /// (the only revision that carries a target OTA)
+  pub fn new(
+    magic1: u32,
+    magic2: u32,
+    minor: u32,
+    device_info: RecoveryDevice,
+    target_ota: Option<u64>,
+  ) -> Result<Self, Error> {
+    let header_rev = match device_info {
+      RecoveryDevice::Device(..) if target_ota.is_some() => {
+        return Err(Error::InvalidHeader("a target OTA requires a platform target (header rev 2)".to_string()));
+      }
+      RecoveryDevice::Device(..) => 1,
+      RecoveryDevice::Platform { .. } => 2,
+    };
+
+    let target_ota = if header_rev == 2 {
+      Some(target_ota.unwrap_or_default())
+    } else {
+      None
+    };
+
+    Ok(Self {
+      md5_hash: String::new(),
+      magic1,
+      magic2,
+      minor,
+      header_rev,
+      device_info,
+      target_ota,
+      unused: RecoveryV1Unused::default(),
+      header_len: None,
     })
   }
 }
 
 impl Display for RecoveryV1 {
   fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
-    writeln!(f, "{:<14} {}", "Bundle Type:", "Recovery V1")?;
+    writeln!(f, "{:<14} Recovery V1", "Bundle Type:")?;
     writeln!(f, "{:<14} {}", "MD5 Hash:", self.md5_hash)?;
     writeln!(f, "{:<14} {}", "Magic 1:", self.magic1)?;
     writeln!(f, "{:<14} {}", "Magic 2:", self.magic2)?;
@@ -639,12 +1261,13 @@ impl Display for RecoveryV1 {
   }
 }
 
-#[derive(Debug, BinRead)]
-#[br(little)]
+#[derive(Debug, BinRead, BinWrite)]
+#[brw(little)]
 struct RecoveryV2Contents {
-  #[br(pad_before = 4)]
+  padding: [u8; 4],
   target_ota: u64,
   #[br(parse_with = parsers::parse_deobfuscated_md5)]
+  #[bw(write_with = writers::write_obfuscated_md5)]
   md5_hash: String,
   magic1: u32,
   magic2: u32,
@@ -652,35 +1275,40 @@ struct RecoveryV2Contents {
   platform_code: u32,
   header_rev: u32,
   board: u32,
-  #[br(pad_before = 7)]
-  #[allow(dead_code)]
+  device_padding: [u8; 7],
   num_devices: u8,
   #[br(count = num_devices)]
   device_codes: Vec<u16>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct RecoveryV2 {
   pub target_ota: u64,
   pub md5_hash: String,
   pub magic1: u32,
   pub magic2: u32,
   pub minor: u32,
+  #[serde(rename = "platform", serialize_with = "serializers::serialize_platform")]
   pub platform_code: u32,
   pub header_rev: u32,
   pub board: u32,
+  #[serde(rename = "devices", serialize_with = "serializers::serialize_devices")]
   pub device_codes: Vec<u16>,
+  #[serde(skip)]
+  padding: [u8; 4],
+  #[serde(skip)]
+  device_padding: [u8; 7],
+  #[serde(skip)]
+  header_len: Option<u64>,
 }
 
 impl BinRead for RecoveryV2 {
   type Args<'a> = ();
 
   fn read_options<R: Read + Seek>(reader: &mut R, _endian: Endian, _args: Self::Args<'_>) -> BinResult<Self> {
-    let mut header_data = vec![0; 131068];
-    reader.read_exact(&mut header_data)?;
-    let mut cursor = Cursor::new(&header_data);
-
-    let contents: RecoveryV2Contents = cursor.read_le()?;
+    let start = reader.stream_position()?;
+    let contents: RecoveryV2Contents = reader.read_le()?;
+    skip_recovery_block(reader, start)?;
 
     Ok(Self {
       target_ota: contents.target_ota,
@@ -692,13 +1320,98 @@ impl BinRead for RecoveryV2 {
       header_rev: contents.header_rev,
       board: contents.board,
       device_codes: contents.device_codes,
+      padding: contents.padding,
+      device_padding: contents.device_padding,
+      header_len: header_len_from(reader, start)?,
     })
   }
 }
 
+// the args are the block of the header this one was parsed from, if any
+impl BinWrite for RecoveryV2 {
+  type Args<'a> = &'a [u8];
+
+  fn write_options<W: Write + Seek>(&self, writer: &mut W, _endian: Endian, source: Self::Args<'_>) -> BinResult<()> {
+    let Ok(num_devices) = u8::try_from(self.device_codes.len()) else {
+      let msg = format!("recovery v2 headers hold at most {} devices, got {}", u8::MAX, self.device_codes.len());
+      return Err(Error::InvalidHeader(msg).into_binrw(writer));
+    };
+
+    let contents = RecoveryV2Contents {
+      padding: self.padding,
+      target_ota: self.target_ota,
+      md5_hash: self.md5_hash.clone(),
+      magic1: self.magic1,
+      magic2: self.magic2,
+      minor: self.minor,
+      platform_code: self.platform_code,
+      header_rev: self.header_rev,
+      board: self.board,
+      device_padding: self.device_padding,
+      num_devices,
+      device_codes: self.device_codes.clone(),
+    };
+
+    let mut fields = Cursor::new(Vec::new());
+    fields.write_le(&contents)?;
+    write_recovery_block(writer, fields.get_ref(), source)?;
+    Ok(())
+  }
+}
+
+impl RecoveryV2 {
+  /// builds a header rev 2 for `platform`/`board` and up to 255 `devices`;
+  /// the md5 hash is filled in by [`create_bundle`]
+  pub fn new(
+    target_ota: u64,
+    magic1: u32,
+    magic2: u32,
+    minor: u32,
+    platform: Platform,
+    board: u32,
+    devices: &[Device],
+  ) -> Result<Self, Error> {
+    if devices.len() > u8::MAX as usize {
+      let msg = format!("recovery v2 headers hold at most {} devices, got {}", u8::MAX, devices.len());
+      return Err(Error::InvalidHeader(msg));
+    }
+
+    Ok(Self {
+      target_ota,
+      md5_hash: String::new(),
+      magic1,
+      magic2,
+      minor,
+      platform_code: platform as u32,
+      header_rev: 2,
+      board,
+      device_codes: devices.iter().map(|&d| d as u16).collect(),
+      padding: [0; 4],
+      device_padding: [0; 7],
+      header_len: None,
+    })
+  }
+
+  /// listed devices known to be built on a different platform than the
+  /// header's. devices of unknown platform are not reported, nor is anything
+  /// when the header names no known platform
+  pub fn foreign_devices(&self) -> Vec<Device> {
+    let platform = match Platform::from_repr(self.platform_code as usize) {
+      None | Some(Platform::PlatUnspecified) | Some(Platform::Unknown) => return Vec::new(),
+      Some(platform) => platform,
+    };
+    self
+      .device_codes
+      .iter()
+      .filter_map(|&code| Device::from_repr(code as usize))
+      .filter(|d| d.platform().is_some_and(|p| p != platform))
+      .collect()
+  }
+}
+
 impl Display for RecoveryV2 {
   fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
-    writeln!(f, "{:<14} {}", "Bundle Type:", "Recovery V2")?;
+    writeln!(f, "{:<14} Recovery V2", "Bundle Type:")?;
     writeln!(f, "{:<14} {}", "Target OTA:", self.target_ota)?;
     writeln!(f, "{:<14} {}", "MD5 Hash:", self.md5_hash)?;
     writeln!(f, "{:<14} {}", "Magic 1:", self.magic1)?;
@@ -722,15 +1435,45 @@ pub struct SignatureEnvelope {
   pub cert_num: u32,
   pub signature: Vec<u8>,
   pub wrapped_bundle: Box<UpdateBundle>,
+  // the block between cert_num and the signature, kept as read so it is written back unchanged
+  reserved: [u8; 56],
+  header_len: Option<u64>,
+}
+
+impl SignatureEnvelope {
+  pub fn new(cert_num: u32, signature: Vec<u8>, wrapped_bundle: UpdateBundle) -> Self {
+    Self {
+      cert_num,
+      signature,
+      wrapped_bundle: Box::new(wrapped_bundle),
+      reserved: [0; 56],
+      header_len: None,
+    }
+  }
+
+  /// size of the rsa signature for `cert_num`: 2048-bit for pubprodkey02, 1024-bit otherwise
+  pub fn signature_size(cert_num: u32) -> usize {
+    if cert_num == 2 { 256 } else { 128 }
+  }
+
+  pub fn cert_file(&self) -> &'static str {
+    match self.cert_num {
+      0x00 => "pubdevkey01.pem (Developer)",
+      0x01 => "pubprodkey01.pem (Official 1K)",
+      0x02 => "pubprodkey02.pem (Official 2K)",
+      _ => "Unknown",
+    }
+  }
 }
 
 impl BinRead for SignatureEnvelope {
   type Args<'a> = ();
   fn read_options<R: Read + Seek>(reader: &mut R, endian: Endian, _args: Self::Args<'_>) -> BinResult<Self> {
+    let start = reader.stream_position()?;
     let cert_num: u32 = reader.read_le()?;
-    reader.seek(SeekFrom::Current(56))?;
-    let sig_size = if cert_num == 2 { 256 } else { 128 };
-    let mut signature = vec![0; sig_size];
+    let mut reserved = [0; 56];
+    reader.read_exact(&mut reserved)?;
+    let mut signature = vec![0; SignatureEnvelope::signature_size(cert_num)];
     reader.read_exact(&mut signature)?;
 
     let wrapped_bundle = Box::new(UpdateBundle::read_options(reader, endian, ())?);
@@ -739,50 +1482,185 @@ impl BinRead for SignatureEnvelope {
       cert_num,
       signature,
       wrapped_bundle,
+      reserved,
+      header_len: header_len_from(reader, start)?,
     })
   }
 }
 
+// the args are passed on to the wrapped bundle
+impl BinWrite for SignatureEnvelope {
+  type Args<'a> = &'a [u8];
+  fn write_options<W: Write + Seek>(&self, writer: &mut W, endian: Endian, source: Self::Args<'_>) -> BinResult<()> {
+    let expected = SignatureEnvelope::signature_size(self.cert_num);
+    if self.signature.len() != expected {
+      let err = Error::SignatureSizeMismatch {
+        cert_num: self.cert_num,
+        expected,
+        got: self.signature.len(),
+      };
+      return Err(err.into_binrw(writer));
+    }
+
+    writer.write_le(&self.cert_num)?;
+    writer.write_all(&self.reserved)?;
+    writer.write_all(&self.signature)?;
+    self.wrapped_bundle.write_options(writer, endian, (source,))
+  }
+}
+
+impl Serialize for SignatureEnvelope {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut st = serializer.serialize_struct("SignatureEnvelope", 4)?;
+    st.serialize_field("cert_num", &self.cert_num)?;
+    st.serialize_field("cert_file", self.cert_file())?;
+    st.serialize_field("signature", &serializers::hex(&self.signature))?;
+    st.serialize_field("wrapped_bundle", &self.wrapped_bundle)?;
+    st.end()
+  }
+}
+
 impl Display for SignatureEnvelope {
   fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
-    let cert_name = match self.cert_num {
-      0x00 => "pubdevkey01.pem (Developer)",
-      0x01 => "pubprodkey01.pem (Official 1K)",
-      0x02 => "pubprodkey02.pem (Official 2K)",
-      _ => "Unknown",
-    };
-    writeln!(f, "{:<14} {}", "Bundle Type:", "Signature Envelope")?;
+    writeln!(f, "{:<14} Signature Envelope", "Bundle Type:")?;
     writeln!(f, "{:<14} {}", "Cert Number:", self.cert_num)?;
-    writeln!(f, "{:<14} {}", "Cert File:", cert_name)?;
+    writeln!(f, "{:<14} {}", "Cert File:", self.cert_file())?;
 
     writeln!(f, "\n--- Wrapped Bundle ---")?;
     write!(f, "{}", self.wrapped_bundle)
   }
 }
 
-#[derive(Debug, BinRead)]
+// writing takes the recovery block of the header the bundle was parsed from,
+// for the bytes its fields don't cover; see `BundleView::write_to`
+#[derive(Debug, BinRead, BinWrite)]
+#[bw(import(recovery_block: &[u8]))]
 pub enum UpdateBundle {
-  #[br(magic = b"SP01")]
-  Signed(Box<SignatureEnvelope>),
-  #[br(magic = b"FC02")]
+  #[brw(magic = b"SP01")]
+  Signed(#[bw(args_raw = recovery_block)] Box<SignatureEnvelope>),
+  #[brw(magic = b"FC02")]
   OtaV1Fc02(OtaV1),
-  #[br(magic = b"FD03")]
+  #[brw(magic = b"FD03")]
   OtaV1Fd03(OtaV1),
-  #[br(magic = b"FC04")]
+  #[brw(magic = b"FC04")]
   OtaV2Fc04(OtaV2),
-  #[br(magic = b"FD04")]
+  #[brw(magic = b"FD04")]
   OtaV2Fd04(OtaV2),
-  #[br(magic = b"FL01")]
+  #[brw(magic = b"FL01")]
   OtaV2Fl01(OtaV2),
-  #[br(magic = b"FB01")]
-  RecoveryV1Fb01(RecoveryV1),
-  #[br(magic = b"FB02")]
-  RecoveryV1Fb02(RecoveryV1),
-  #[br(magic = b"FB03")]
-  RecoveryV2Fb03(RecoveryV2),
+  #[brw(magic = b"FB01")]
+  RecoveryV1Fb01(#[bw(args_raw = recovery_block)] RecoveryV1),
+  #[brw(magic = b"FB02")]
+  RecoveryV1Fb02(#[bw(args_raw = recovery_block)] RecoveryV1),
+  #[brw(magic = b"FB03")]
+  RecoveryV2Fb03(#[bw(args_raw = recovery_block)] RecoveryV2),
+  /// fallback for unrecognized magics, keeping the leading header bytes for triage
+  Unknown {
+    // known magics that failed to parse must report their own error
+    #[br(assert(!UpdateBundle::MAGICS.contains(&&magic)))]
+    magic: [u8; 4],
+    #[br(parse_with = parsers::parse_up_to, args(UNKNOWN_HEADER_PREVIEW))]
+    header: Vec<u8>,
+  },
+}
+
+// header bytes kept by `UpdateBundle::Unknown`
+const UNKNOWN_HEADER_PREVIEW: u64 = 64;
+
+// records how far a failed read wanted to go, so running out of input
+// mid-header can be reported as a truncated header
+struct EofTracker<'a, R> {
+  inner: &'a mut R,
+  pos: u64,
+  wanted: Option<u64>,
+}
+
+impl<R: Read> Read for EofTracker<'_, R> {
+  fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+    let bytes_read = self.inner.read(buf)?;
+    self.pos += bytes_read as u64;
+    Ok(bytes_read)
+  }
+
+  fn read_exact(&mut self, buf: &mut [u8]) -> std::io::Result<()> {
+    let end = self.pos + buf.len() as u64;
+    match self.inner.read_exact(buf) {
+      Ok(()) => {
+        self.pos = end;
+        Ok(())
+      }
+      Err(e) => {
+        if e.kind() == std::io::ErrorKind::UnexpectedEof {
+          self.wanted = Some(self.wanted.map_or(end, |w| w.max(end)));
+        }
+        Err(e)
+      }
+    }
+  }
+}
+
+impl<R: Seek> Seek for EofTracker<'_, R> {
+  fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+    self.pos = self.inner.seek(pos)?;
+    Ok(self.pos)
+  }
 }
 
 impl UpdateBundle {
+  pub const MAGICS: [&[u8; 4]; 9] = [b"SP01", b"FC02", b"FD03", b"FC04", b"FD04", b"FL01", b"FB01", b"FB02", b"FB03"];
+
+  /// parses a bundle header, leaving `reader` at the start of the payload.
+  /// unrecognized magics parse as [`UpdateBundle::Unknown`]
+  pub fn read_from<R: Read + Seek>(reader: &mut R) -> Result<Self, Error> {
+    let start = reader.stream_position()?;
+    let mut tracker = EofTracker {
+      inner: reader,
+      pos: start,
+      wanted: None,
+    };
+
+    let result: BinResult<Self> = tracker.read_le();
+    match (result.map_err(Error::from), tracker.wanted) {
+      (Err(e), Some(wanted)) if e.is_eof() => {
+        let end = reader.seek(SeekFrom::End(0))?;
+        // byte-wise magic reads give up early, but every bundle needs its 4-byte magic
+        Err(Error::TruncatedHeader {
+          expected: (wanted - start).max(4),
+          got: end - start,
+        })
+      }
+      (result, _) => result,
+    }
+  }
+
+  // like `read_with_payload`, refusing bundles whose payload can't be interpreted
+  pub(crate) fn read_supported<R: Read + Seek>(reader: R) -> Result<(Self, PayloadReader<R>), Error> {
+    let (bundle, payload) = Self::read_with_payload(reader)?;
+    if let UpdateBundle::Unknown { magic, .. } = bundle.innermost() {
+      return Err(Error::UnknownMagic(*magic));
+    }
+    Ok((bundle, payload))
+  }
+
+  /// the bundle inside any signature envelopes
+  pub fn innermost(&self) -> &UpdateBundle {
+    match self {
+      UpdateBundle::Signed(p) => p.wrapped_bundle.innermost(),
+      bundle => bundle,
+    }
+  }
+
+  /// known magics sharing the leading two characters of `magic`, closest revision first
+  pub fn neighbours(magic: &[u8; 4]) -> Vec<&'static str> {
+    let revision = |m: &[u8]| std::str::from_utf8(m).ok().and_then(|r| r.parse::<i32>().ok());
+    let mut neighbours: Vec<_> = Self::MAGICS.iter().filter(|m| m[..2] == magic[..2]).collect();
+    neighbours.sort_by_key(|m| match (revision(&m[2..]), revision(&magic[2..])) {
+      (Some(a), Some(b)) => (a - b).abs(),
+      _ => i32::MAX,
+    });
+    neighbours.into_iter().map(|m| std::str::from_utf8(*m).unwrap_or_default()).collect()
+  }
+
   pub fn magic_str(&self) -> &'static str {
     match self {
       UpdateBundle::Signed(_) => "SP01",
@@ -794,6 +1672,7 @@ impl UpdateBundle {
       UpdateBundle::RecoveryV1Fb01(_) => "FB01",
       UpdateBundle::RecoveryV1Fb02(_) => "FB02",
       UpdateBundle::RecoveryV2Fb03(_) => "FB03",
+      UpdateBundle::Unknown { .. } => "????",
     }
   }
 
@@ -808,10 +1687,96 @@ impl UpdateBundle {
       _ => "Unknown",
     }
   }
+
+  /// md5 hash of the deobfuscated payload, taken from the innermost bundle
+  pub fn md5_hash(&self) -> Option<&str> {
+    match self {
+      UpdateBundle::Signed(p) => p.wrapped_bundle.md5_hash(),
+      UpdateBundle::OtaV1Fc02(p) | UpdateBundle::OtaV1Fd03(p) => Some(&p.md5_hash),
+      UpdateBundle::OtaV2Fc04(p) | UpdateBundle::OtaV2Fd04(p) | UpdateBundle::OtaV2Fl01(p) => Some(&p.md5_hash),
+      UpdateBundle::RecoveryV1Fb01(p) | UpdateBundle::RecoveryV1Fb02(p) => Some(&p.md5_hash),
+      UpdateBundle::RecoveryV2Fb03(p) => Some(&p.md5_hash),
+      UpdateBundle::Unknown { .. } => None,
+    }
+  }
+
+  fn md5_hash_mut(&mut self) -> Option<&mut String> {
+    match self {
+      UpdateBundle::Signed(_) | UpdateBundle::Unknown { .. } => None,
+      UpdateBundle::OtaV1Fc02(p) | UpdateBundle::OtaV1Fd03(p) => Some(&mut p.md5_hash),
+      UpdateBundle::OtaV2Fc04(p) | UpdateBundle::OtaV2Fd04(p) | UpdateBundle::OtaV2Fl01(p) => Some(&mut p.md5_hash),
+      UpdateBundle::RecoveryV1Fb01(p) | UpdateBundle::RecoveryV1Fb02(p) => Some(&mut p.md5_hash),
+      UpdateBundle::RecoveryV2Fb03(p) => Some(&mut p.md5_hash),
+    }
+  }
+}
+
+impl Serialize for UpdateBundle {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut st = serializer.serialize_struct("UpdateBundle", 3)?;
+    match self {
+      UpdateBundle::Unknown { magic, .. } => st.serialize_field("magic", &printable(magic))?,
+      bundle => st.serialize_field("magic", bundle.magic_str())?,
+    }
+    match self {
+      UpdateBundle::Signed(p) => {
+        st.serialize_field("bundle_type", "signature_envelope")?;
+        st.serialize_field("header", p)?;
+      }
+      UpdateBundle::OtaV1Fc02(p) | UpdateBundle::OtaV1Fd03(p) => {
+        st.serialize_field("bundle_type", "ota_v1")?;
+        st.serialize_field("header", p)?;
+      }
+      UpdateBundle::OtaV2Fc04(p) | UpdateBundle::OtaV2Fd04(p) | UpdateBundle::OtaV2Fl01(p) => {
+        st.serialize_field("bundle_type", "ota_v2")?;
+        st.serialize_field("header", p)?;
+      }
+      UpdateBundle::RecoveryV1Fb01(p) | UpdateBundle::RecoveryV1Fb02(p) => {
+        st.serialize_field("bundle_type", "recovery_v1")?;
+        st.serialize_field("header", p)?;
+      }
+      UpdateBundle::RecoveryV2Fb03(p) => {
+        st.serialize_field("bundle_type", "recovery_v2")?;
+        st.serialize_field("header", p)?;
+      }
+      UpdateBundle::Unknown { magic, header } => {
+        st.serialize_field("bundle_type", "unknown")?;
+        st.serialize_field("header", &serializers::unknown(magic, header))?;
+      }
+    }
+    st.end()
+  }
+}
+
+// ascii rendering with non-printable bytes replaced by '.'
+pub(crate) fn printable(bytes: &[u8]) -> String {
+  bytes.iter().map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' }).collect()
+}
+
+fn write_hex_dump(f: &mut Formatter<'_>, bytes: &[u8]) -> FmtResult {
+  for (i, line) in bytes.chunks(16).enumerate() {
+    let hex: Vec<_> = line.iter().map(|b| format!("{:02X}", b)).collect();
+    let ascii = printable(line);
+    write!(f, "\n  {:04X}  {:<47}  |{}|", i * 16, hex.join(" "), ascii)?;
+  }
+  Ok(())
 }
 
 impl Display for UpdateBundle {
   fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+    if let UpdateBundle::Unknown { magic, header } = self {
+      let neighbours = UpdateBundle::neighbours(magic);
+      writeln!(f, "{:<14} {} (Unknown)", "Bundle Magic:", printable(magic))?;
+      writeln!(f, "{:<14} {:02X?}", "Magic Bytes:", magic)?;
+      if neighbours.is_empty() {
+        writeln!(f, "{:<14} no known magic shares the '{}' prefix", "Hint:", printable(&magic[..2]))?;
+      } else {
+        writeln!(f, "{:<14} possibly a new revision of {}", "Hint:", neighbours.join(", "))?;
+      }
+      write!(f, "{:<14} {} bytes", "Header Bytes:", header.len())?;
+      return write_hex_dump(f, header);
+    }
+
     writeln!(f, "{:<14} {} {}", "Bundle Magic:", self.magic_str(), self.description())?;
     match self {
       UpdateBundle::Signed(p) => write!(f, "{}", p),
@@ -819,26 +1784,380 @@ impl Display for UpdateBundle {
       UpdateBundle::OtaV2Fc04(p) | UpdateBundle::OtaV2Fd04(p) | UpdateBundle::OtaV2Fl01(p) => write!(f, "{}", p),
       UpdateBundle::RecoveryV1Fb01(p) | UpdateBundle::RecoveryV1Fb02(p) => write!(f, "{}", p),
       UpdateBundle::RecoveryV2Fb03(p) => write!(f, "{}", p),
+      UpdateBundle::Unknown { .. } => Ok(()),
     }
   }
 }
 
-pub fn dump_payload<R: Read + Seek, W: Write>(reader: &mut R, writer: &mut W) -> Result<(), Box<dyn std::error::Error>> {
-  const BUFFER_SIZE: usize = 8192;
+pub fn dump_payload<R: Read + Seek, W: Write>(reader: &mut R, writer: &mut W) -> Result<(), Error> {
+  let (_bundle, payload) = UpdateBundle::read_supported(reader)?;
+  std::io::copy(&mut DeobfuscatingReader::new(payload), writer)?;
+  Ok(())
+}
 
-  let _bundle: UpdateBundle = reader.read_le()?;
+fn md5_hex<R: Read>(reader: &mut R) -> std::io::Result<String> {
+  let mut hasher = Md5::new();
+  std::io::copy(reader, &mut hasher)?;
+  Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
 
-  let mut buffer = [0; BUFFER_SIZE];
-  loop {
-    let bytes_read = reader.read(&mut buffer)?;
-    if bytes_read == 0 {
-      break;
-    }
+#[derive(Debug, Clone, Serialize)]
+pub struct PayloadMd5 {
+  /// hash stored in the bundle header
+  pub expected: String,
+  /// hash of the deobfuscated payload
+  pub actual: String,
+}
 
-    let chunk = &mut buffer[..bytes_read];
-    deobfuscate_in_place(chunk);
-    writer.write_all(chunk)?;
+impl PayloadMd5 {
+  pub fn is_match(&self) -> bool {
+    self.expected.eq_ignore_ascii_case(&self.actual)
   }
+}
 
+/// parses the bundle read from `reader` and hashes the payload that follows it
+pub fn verify_payload_md5<R: Read + Seek>(reader: &mut R) -> Result<PayloadMd5, Error> {
+  let (bundle, payload) = UpdateBundle::read_supported(reader)?;
+  let actual = md5_hex(&mut DeobfuscatingReader::new(payload))?;
+  Ok(PayloadMd5 {
+    expected: bundle.md5_hash().unwrap_or_default().to_string(),
+    actual,
+  })
+}
+
+/// writes `bundle` followed by the already obfuscated `payload` unchanged,
+/// e.g. to re-emit a parsed bundle after patching a header field. the
+/// reserved rest of recovery headers is not kept by parsing and is written as
+/// zeros; [`BundleView::write_to`] reproduces it
+pub fn write_bundle<R: Read, W: Write>(
+  bundle: &UpdateBundle,
+  payload: &mut R,
+  writer: &mut W,
+) -> Result<(), Error> {
+  write_header(bundle, &[], writer)?;
+  std::io::copy(payload, writer)?;
   Ok(())
-}
\ No newline at end of file
+}
+
+// `recovery_block` fills the bytes of a recovery header block past its fields
+pub(crate) fn write_header<W: Write>(bundle: &UpdateBundle, recovery_block: &[u8], writer: &mut W) -> Result<(), Error> {
+  NoSeek::new(writer).write_le_args(bundle, (recovery_block,))?;
+  Ok(())
+}
+
+/// writes `bundle` followed by the obfuscated `payload`, filling in the payload md5 hash
+pub fn create_bundle<R: Read + Seek, W: Write>(
+  mut bundle: UpdateBundle,
+  payload: &mut R,
+  writer: &mut W,
+) -> Result<(), Error> {
+  let magic = bundle.magic_str();
+  let Some(md5_hash) = bundle.md5_hash_mut() else {
+    return Err(Error::UnsupportedBundle(magic));
+  };
+
+  let payload_start = payload.stream_position()?;
+  *md5_hash = md5_hex(payload)?;
+  payload.seek(SeekFrom::Start(payload_start))?;
+
+  let mut writer = NoSeek::new(writer);
+  writer.write_le(&bundle)?;
+  std::io::copy(payload, &mut ObfuscatingWriter::new(&mut writer))?;
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const MD5: &[u8; 32] = b"0123456789abcdef0123456789abcdef";
+
+  fn obfuscated(bytes: &[u8]) -> Vec<u8> {
+    let mut bytes = bytes.to_vec();
+    obfuscate_in_place(&mut bytes);
+    bytes
+  }
+
+  // non-zero bytes for the areas a zero-filling writer would lose
+  fn filler(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 7 + 1) as u8).collect()
+  }
+
+  // parses `header`, which must hold nothing but the header, and writes it back
+  fn round_trip(header: &[u8]) -> UpdateBundle {
+    let view = UpdateBundle::parse_bytes(header).unwrap();
+    assert!(view.payload.is_empty());
+
+    let mut written = Vec::new();
+    view.write_to(&mut written).unwrap();
+    assert_eq!(written, header);
+    view.bundle
+  }
+
+  fn ota_v1(magic: &[u8; 4]) -> Vec<u8> {
+    let mut header = magic.to_vec();
+    header.extend(obfuscated(MD5));
+    header.extend(1u32.to_le_bytes());
+    header.extend(2u32.to_le_bytes());
+    header.extend((Device::Kindle3WiFi as u16).to_le_bytes());
+    header.extend([1, 0x5A]);
+    header
+  }
+
+  fn ota_v2(magic: &[u8; 4], metadata: &[&[u8]]) -> Vec<u8> {
+    let mut header = magic.to_vec();
+    header.extend(3u64.to_le_bytes());
+    header.extend(u64::MAX.to_le_bytes());
+    header.extend(2u16.to_le_bytes());
+    header.extend((Device::KindlePaperWhite5 as u16).to_le_bytes());
+    header.extend((Device::KindleColorSoftUnknown_3H7 as u16).to_le_bytes());
+    header.extend([1, 0x5A]);
+    header.extend(obfuscated(MD5));
+    header.extend((metadata.len() as u16).to_le_bytes());
+    for meta in metadata {
+      header.extend((meta.len() as u16).to_be_bytes());
+      header.extend(obfuscated(meta));
+    }
+    header
+  }
+
+  fn recovery_v1(magic: &[u8; 4], header_rev: u32, code: u32) -> Vec<u8> {
+    let mut block = vec![0xA5, 0x5A, 0x01, 0x02];
+    block.extend(0x0102_0304_0506_0708u64.to_le_bytes());
+    block.extend(obfuscated(MD5));
+    for value in [1, 2, 3, code, header_rev, 0x0B] {
+      block.extend(value.to_le_bytes());
+    }
+    block.extend(filler(RECOVERY_HEADER_SIZE - block.len()));
+    [magic.to_vec(), block].concat()
+  }
+
+  fn recovery_v2(devices: &[Device]) -> Vec<u8> {
+    let mut block = vec![0xA5, 0x5A, 0x01, 0x02];
+    block.extend(7u64.to_le_bytes());
+    block.extend(obfuscated(MD5));
+    for value in [1, 2, 3, Platform::Bellatrix4 as u32, 2, 0x0B] {
+      block.extend(value.to_le_bytes());
+    }
+    block.extend([1, 2, 3, 4, 5, 6, 7]);
+    block.push(devices.len() as u8);
+    for &device in devices {
+      block.extend((device as u16).to_le_bytes());
+    }
+    block.extend(filler(RECOVERY_HEADER_SIZE - block.len()));
+    [b"FB03".to_vec(), block].concat()
+  }
+
+  fn signed(cert_num: u32, wrapped: &[u8]) -> Vec<u8> {
+    let mut header = b"SP01".to_vec();
+    header.extend(cert_num.to_le_bytes());
+    header.extend(filler(56));
+    header.extend(vec![0xEE; SignatureEnvelope::signature_size(cert_num)]);
+    header.extend(wrapped);
+    header
+  }
+
+  #[test]
+  fn ota_v1_round_trips() {
+    for magic in [b"FC02", b"FD03"] {
+      let bundle = round_trip(&ota_v1(magic));
+      assert_eq!(bundle.magic_str().as_bytes(), magic);
+    }
+  }
+
+  #[test]
+  fn ota_v2_round_trips() {
+    for magic in [b"FC04", b"FD04", b"FL01"] {
+      let bundle = round_trip(&ota_v2(magic, &[b"key=value", b""]));
+      assert_eq!(bundle.magic_str().as_bytes(), magic);
+    }
+  }
+
+  #[test]
+  fn non_utf8_metadata_round_trips() {
+    let header = ota_v2(b"FC04", &[&[0xC3, 0x28]]);
+    let UpdateBundle::OtaV2Fc04(ota) = round_trip(&header) else {
+      panic!("expected an FC04 bundle");
+    };
+    assert_eq!(ota.metadata[0].0, [0xC3, 0x28]);
+    assert_eq!(ota.metadata[0].to_string(), "\u{FFFD}(");
+  }
+
+  #[test]
+  fn ota_v2_counts_follow_edited_lists() {
+    let mut reader = Cursor::new(ota_v2(b"FC04", &[b"a=b"]));
+    let mut bundle = UpdateBundle::read_from(&mut reader).unwrap();
+    let UpdateBundle::OtaV2Fc04(ota) = &mut bundle else {
+      panic!("expected an FC04 bundle");
+    };
+    ota.device_codes.push(Device::KindleScribe_23L as u16);
+    ota.metadata.clear();
+
+    let mut written = Vec::new();
+    write_bundle(&bundle, &mut std::io::empty(), &mut written).unwrap();
+    let UpdateBundle::OtaV2Fc04(ota) = UpdateBundle::read_from(&mut Cursor::new(written)).unwrap() else {
+      panic!("expected an FC04 bundle");
+    };
+    assert_eq!(ota.device_codes.len(), 3);
+    assert!(ota.metadata.is_empty());
+  }
+
+  #[test]
+  fn recovery_v1_round_trips() {
+    for magic in [b"FB01", b"FB02"] {
+      round_trip(&recovery_v1(magic, 1, 0xABCD_0000 | Device::Kindle3WiFi as u32));
+      round_trip(&recovery_v1(magic, 2, Platform::Luigi as u32));
+    }
+  }
+
+  #[test]
+  fn recovery_v2_round_trips() {
+    round_trip(&recovery_v2(&[]));
+    round_trip(&recovery_v2(&[Device::KindleColorSoftUnknown_3H7, Device::KindleScribe2Unknown_3V0]));
+  }
+
+  #[test]
+  fn recovery_reserved_bytes_come_from_the_view() {
+    let header = signed(0, &recovery_v2(&[Device::KindleScribe_23L]));
+    let mut view = UpdateBundle::parse_bytes(&header).unwrap();
+
+    // without the view the reserved rest of the block is zeroed
+    let mut written = Vec::new();
+    write_bundle(&view.bundle, &mut std::io::empty(), &mut written).unwrap();
+    assert_eq!(written.len(), header.len());
+    assert!(written[header.len() - 100..].iter().all(|&b| b == 0));
+
+    // edited fields overwrite the start of the reserved bytes, the rest stays in place
+    let UpdateBundle::Signed(envelope) = &mut view.bundle else {
+      panic!("expected an SP01 bundle");
+    };
+    let UpdateBundle::RecoveryV2Fb03(recovery) = envelope.wrapped_bundle.as_mut() else {
+      panic!("expected an FB03 bundle");
+    };
+    recovery.device_codes.push(Device::KindleColorSoftUnknown_3H7 as u16);
+    let mut written = Vec::new();
+    view.write_to(&mut written).unwrap();
+    assert_eq!(written.len(), header.len());
+    let fields_end = header.len() - RECOVERY_HEADER_SIZE + 4 + 8 + 32 + 6 * 4 + 8 + 2 * 2;
+    assert_eq!(written[fields_end..], header[fields_end..]);
+  }
+
+  #[test]
+  fn signed_round_trips() {
+    for cert_num in [0, 1, 2] {
+      let bundle = round_trip(&signed(cert_num, &ota_v2(b"FC04", &[b"a=b"])));
+      assert_eq!(bundle.innermost().magic_str(), "FC04");
+    }
+    round_trip(&signed(0, &signed(2, &recovery_v2(&[Device::KindleScribe_23L]))));
+  }
+
+  #[test]
+  fn unknown_round_trips() {
+    let header = [b"FC05".to_vec(), filler(UNKNOWN_HEADER_PREVIEW as usize)].concat();
+    let bundle = round_trip(&header);
+    assert!(matches!(bundle, UpdateBundle::Unknown { .. }));
+  }
+
+  #[test]
+  fn created_bundles_parse_back() {
+    let payload = b"not really a tarball";
+    let bundles = [
+      UpdateBundle::OtaV1Fc02(OtaV1::new(1, 2, Device::Kindle3WiFi, false).unwrap()),
+      UpdateBundle::OtaV2Fc04(OtaV2::new(1, 2, &[Device::KindlePaperWhite5], true, vec!["a=b".to_string()]).unwrap()),
+      UpdateBundle::RecoveryV1Fb02(RecoveryV1::new(1, 2, 3, RecoveryDevice::Device(Device::Kindle3WiFi, 0x08), None).unwrap()),
+      UpdateBundle::RecoveryV2Fb03(RecoveryV2::new(7, 1, 2, 3, Platform::Bellatrix4, 0, &[Device::KindleScribe_23L]).unwrap()),
+    ];
+    for bundle in bundles {
+      let magic = bundle.magic_str();
+      let mut written = Vec::new();
+      create_bundle(bundle, &mut Cursor::new(payload), &mut written).unwrap();
+
+      let mut reader = Cursor::new(&written);
+      let parsed = UpdateBundle::read_from(&mut reader).unwrap();
+      assert_eq!(parsed.magic_str(), magic);
+      let md5 = verify_payload_md5(&mut Cursor::new(&written)).unwrap();
+      assert!(md5.is_match(), "{}", magic);
+      round_trip(&written[..reader.position() as usize]);
+    }
+  }
+
+  #[test]
+  fn header_len_is_where_parsing_stopped() {
+    let inner = ota_v2(b"FC04", &[&[0xC3, 0x28], b"a=b"]);
+    let headers = [
+      ota_v1(b"FC02"),
+      inner.clone(),
+      recovery_v1(b"FB02", 2, Platform::Luigi as u32),
+      recovery_v2(&[Device::KindleScribe_23L]),
+      signed(0, &signed(2, &inner)),
+    ];
+    for header in headers {
+      let bundle_len = header.len() as u64 + 5;
+      let bundle = UpdateBundle::read_from(&mut Cursor::new([header.clone(), vec![0; 5]].concat())).unwrap();
+      assert_eq!(bundle.header_len(), Some(header.len() as u64), "{}", bundle.magic_str());
+      assert_eq!(bundle.payload_range(bundle_len), Some(header.len() as u64..bundle_len));
+    }
+
+    let bundle = UpdateBundle::read_from(&mut Cursor::new(signed(0, &signed(2, &inner)))).unwrap();
+    let UpdateBundle::Signed(outer) = &bundle else {
+      panic!("expected an SP01 bundle");
+    };
+    let UpdateBundle::Signed(middle) = outer.wrapped_bundle.as_ref() else {
+      panic!("expected a nested SP01 bundle");
+    };
+    assert_eq!(middle.wrapped_bundle.header_len(), Some(inner.len() as u64));
+    assert_eq!(outer.wrapped_bundle.header_len(), Some(middle.envelope_len() + inner.len() as u64));
+  }
+
+  #[test]
+  fn header_len_is_recorded_by_binread() {
+    let inner = recovery_v1(b"FB01", 1, Device::Kindle3WiFi as u32);
+    let header = signed(1, &inner);
+    let bundle: UpdateBundle = Cursor::new([header.clone(), vec![0; 5]].concat()).read_le().unwrap();
+    assert_eq!(bundle.header_len(), Some(header.len() as u64));
+    let UpdateBundle::Signed(envelope) = &bundle else {
+      panic!("expected an SP01 bundle");
+    };
+    assert_eq!(envelope.wrapped_bundle.header_len(), Some(inner.len() as u64));
+
+    let header = ota_v1(b"FD03");
+    let bundle: UpdateBundle = Cursor::new(&header).read_le().unwrap();
+    assert_eq!(bundle.header_len(), Some(header.len() as u64));
+  }
+
+  #[test]
+  fn header_len_is_unknown_unless_parsed() {
+    let header = [b"FC05".to_vec(), filler(200)].concat();
+    let bundle = UpdateBundle::read_from(&mut Cursor::new(header)).unwrap();
+    assert_eq!(bundle.header_len(), None);
+    assert_eq!(bundle.payload_range(204), None);
+
+    let bundle = UpdateBundle::OtaV1Fc02(OtaV1::new(1, 2, Device::Kindle3WiFi, false).unwrap());
+    assert_eq!(bundle.header_len(), None);
+  }
+
+  #[test]
+  fn legacy_serials_decode_hex_codes() {
+    assert_eq!(Device::from_serial("B00E1234567890AB"), Some(Device::Kindle4NonTouch));
+    assert_eq!(Device::from_serial("9023 1234 5678 90ab"), Some(Device::Kindle4NonTouchBlack));
+    assert_eq!(Device::from_serial("B0ZZ1234567890AB"), None);
+  }
+
+  #[test]
+  fn base32_serials_decode_three_digit_codes() {
+    assert_eq!(Device::from_serial("G093H7A112345678"), Some(Device::KindleColorSoftUnknown_3H7));
+    assert_eq!(Device::from_serial("g093 h7a1 1234 5678"), Some(Device::KindleColorSoftUnknown_3H7));
+    // I and O are not part of the alphabet
+    assert_eq!(Device::from_serial("G09IO0000000000"), None);
+    assert_eq!(Device::from_serial("G09"), None);
+  }
+
+  #[test]
+  fn root_passwords_follow_both_schemes() {
+    // md5("B00E1234567890AB\n") = b703518d0593c2f64aed382b048fd6f5
+    assert_eq!(root_password("B00E1234567890AB", PasswordScheme::Legacy), "fionad059");
+    assert_eq!(root_password("B00E1234567890AB", PasswordScheme::Fw5), "fiona2f6");
+    assert_eq!(root_password("9023 1234 5678 90ab", PasswordScheme::Legacy), "fiona5351");
+    assert_eq!(root_password("G093H7A112345678", PasswordScheme::Fw5), "fiona992");
+  }
+}
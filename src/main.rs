@@ -1,9 +1,11 @@
-use binrw::BinReaderExt;
-use clap::{Parser, Subcommand};
-use kindle_tool::UpdateBundle;
+use clap::{Parser, Subcommand, ValueEnum};
+use kindle_tool::{Device, Error, OtaV1, OtaV2, PasswordScheme, PayloadFormat, Platform, RecoveryDevice, RecoveryV1, RecoveryV2, SignatureStatus, UpdateBundle};
+#[cfg(unix)]
+use memmap2::Advice;
+use memmap2::Mmap;
 use std::fs::File;
-use std::io::{self, BufReader, BufWriter, Read, Write};
-use std::path::PathBuf;
+use std::io::{self, BufReader, BufWriter, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
 use std::process;
 
 #[derive(Parser, Debug)]
@@ -16,20 +18,55 @@ struct Cli {
 #[derive(Subcommand, Debug)]
 enum Commands {
   /// display the metadata of a firmware file
-  #[command(visible_alias = "info")]
   Inspect {
     /// kindle firmware (.bin) file to inspect
     input_file: PathBuf,
+
+    /// hash the payload and check it against the md5 stored in the header
+    #[arg(long)]
+    verify: bool,
+
+    /// output format
+    #[arg(short, long, value_enum, default_value_t = Format::Text)]
+    format: Format,
   },
 
-  /// extract the deobfuscated tar.gz payload from a firmware file
+  /// extract the deobfuscated payload from a firmware file
   #[command(visible_alias = "convert")]
   Dump {
     /// kindle firmware (.bin) file to process
     input_file: PathBuf,
 
-    /// output file for the .tar.gz payload [default: stdout]
+    /// output file for the payload [default: stdout, or <INPUT_FILE> with an extension
+    /// matching the payload format when stdout is a terminal]
     output_file: Option<PathBuf>,
+
+    /// threads to (de)obfuscate on; 0 uses one per cpu
+    #[arg(short, long, default_value_t = 1, value_parser = parse_jobs)]
+    jobs: usize,
+  },
+
+  /// unpack the payload of a firmware file into a directory
+  Extract {
+    /// kindle firmware (.bin) file to process
+    input_file: PathBuf,
+
+    /// directory to unpack the payload into (created if missing)
+    output_dir: PathBuf,
+
+    /// skip the per-file .sig signatures
+    #[arg(long)]
+    no_sigs: bool,
+  },
+
+  /// list the payload contents of a firmware file without extracting them
+  Ls {
+    /// kindle firmware (.bin) file to list
+    input_file: PathBuf,
+
+    /// only show update scripts (*.ffs, *.sh) or only data files
+    #[arg(long, value_enum)]
+    only: Option<EntryFilter>,
   },
 
   /// deobfuscate a data stream
@@ -39,6 +76,10 @@ enum Commands {
 
     /// file to write deobfuscated data to [default: stdout]
     output_file: Option<PathBuf>,
+
+    /// threads to (de)obfuscate on; 0 uses one per cpu
+    #[arg(short, long, default_value_t = 1, value_parser = parse_jobs)]
+    jobs: usize,
   },
 
   /// obfuscate a data stream
@@ -48,10 +89,331 @@ enum Commands {
 
     /// file to write obfuscated data to [default: stdout]
     output_file: Option<PathBuf>,
+
+    /// threads to (de)obfuscate on; 0 uses one per cpu
+    #[arg(short, long, default_value_t = 1, value_parser = parse_jobs)]
+    jobs: usize,
+  },
+
+  /// wrap a firmware file in a signature envelope (SP01)
+  Sign {
+    /// kindle firmware (.bin) file to sign
+    input_file: PathBuf,
+
+    /// output file for the signed firmware [default: stdout]
+    output_file: Option<PathBuf>,
+
+    /// rsa private key (pkcs#1 or pkcs#8 pem) to sign with
+    #[arg(short, long)]
+    key: PathBuf,
+
+    /// certificate number the device checks the signature against
+    /// (0: pubdevkey01.pem, 1: pubprodkey01.pem, 2: pubprodkey02.pem)
+    #[arg(short, long, default_value_t = 0)]
+    cert_num: u32,
+  },
+
+  /// check the signature of a signed firmware file (SP01)
+  Verify {
+    /// signed kindle firmware (.bin) file to check
+    input_file: PathBuf,
+
+    /// rsa public key (pem) to check against; may be repeated
+    #[arg(short, long = "pubkey", required = true)]
+    pubkeys: Vec<PathBuf>,
+  },
+
+  /// strip the signature envelope (SP01) from a signed firmware file
+  Unwrap {
+    /// signed kindle firmware (.bin) file to unwrap
+    input_file: PathBuf,
+
+    /// output file for the inner firmware
+    output_file: PathBuf,
+
+    /// output file for the raw signature [default: <OUTPUT_FILE>.sig];
+    /// nested envelopes get a layer suffix (.1, .2, ...)
+    #[arg(short, long)]
+    signature: Option<PathBuf>,
+
+    /// strip every nested envelope rather than only the outermost one
+    #[arg(short, long)]
+    all: bool,
+  },
+
+  /// identify the device a serial number belongs to and its default root password.
+  /// given a firmware file instead, inspect it (`info` used to be an alias of `inspect`)
+  Info {
+    /// kindle serial number (spaces are ignored), or a firmware (.bin) file to inspect
+    serial: String,
+
+    /// when inspecting a firmware file, also check the payload md5
+    #[arg(long)]
+    verify: bool,
+
+    /// output format
+    #[arg(short, long, value_enum, default_value_t = Format::Text)]
+    format: Format,
+  },
+
+  /// list the known devices with their codes, serial prefixes and platforms
+  Devices {
+    /// only list devices whose name contains this (case-insensitive)
+    #[arg(short, long)]
+    name: Option<String>,
+
+    /// only list devices built on this platform (name or code)
+    #[arg(short, long, value_parser = parse_platform)]
+    platform: Option<Platform>,
+
+    /// only list the device with this code
+    #[arg(short, long, value_parser = parse_code)]
+    code: Option<u64>,
+
+    /// output format
+    #[arg(short, long, value_enum, default_value_t = Format::Text)]
+    format: Format,
+  },
+
+  /// build a firmware file from a payload
+  Create {
+    #[command(subcommand)]
+    bundle: CreateCommands,
+  },
+}
+
+#[derive(Subcommand, Debug)]
+enum CreateCommands {
+  /// build a legacy OTA V1 (FC02/FD03) firmware file
+  Ota {
+    /// tar.gz payload to package
+    input_file: PathBuf,
+
+    /// output file for the firmware [default: stdout]
+    output_file: Option<PathBuf>,
+
+    /// bundle magic to write
+    #[arg(short, long, value_enum, default_value_t = OtaMagic::Fc02)]
+    magic: OtaMagic,
+
+    /// target device: name, alias (e.g. kpw2) or code, hex (0x...) or decimal
+    #[arg(short, long, value_parser = parse_device)]
+    device: Device,
+
+    /// minimum OTA revision the update applies to
+    #[arg(short, long, default_value_t = 0)]
+    source_rev: u64,
+
+    /// OTA revision the update brings the device to
+    #[arg(short, long, default_value_t = u32::MAX as u64)]
+    target_rev: u64,
+
+    /// mark the update as optional
+    #[arg(short, long)]
+    optional: bool,
+  },
+
+  /// build an OTA V2 (FC04/FD04) firmware file
+  Ota2 {
+    /// tar.gz payload to package
+    input_file: PathBuf,
+
+    /// output file for the firmware [default: stdout]
+    output_file: Option<PathBuf>,
+
+    /// bundle magic to write
+    #[arg(short, long, value_enum, default_value_t = Ota2Magic::Fc04)]
+    magic: Ota2Magic,
+
+    /// target device: name, alias (e.g. kpw5), group (e.g. all-kpw5) or code,
+    /// hex (0x...) or decimal; may be repeated
+    #[arg(short, long = "device", value_parser = parse_device_group, required = true)]
+    devices: Vec<DeviceGroup>,
+
+    /// minimum OTA revision the update applies to
+    #[arg(short, long, default_value_t = 0)]
+    source_rev: u64,
+
+    /// OTA revision the update brings the device to
+    #[arg(short, long, default_value_t = u64::MAX)]
+    target_rev: u64,
+
+    /// mark the update as critical
+    #[arg(short, long)]
+    critical: bool,
+
+    /// metadata string (e.g. key=value); may be repeated
+    #[arg(long = "meta")]
+    metadata: Vec<String>,
+  },
+
+  /// build a recovery V1 (FB01/FB02) fullbin firmware file
+  Recovery {
+    /// payload to package
+    input_file: PathBuf,
+
+    /// output file for the firmware [default: stdout]
+    output_file: Option<PathBuf>,
+
+    /// bundle magic to write
+    #[arg(short, long, value_enum, default_value_t = RecoveryMagic::Fb02)]
+    magic: RecoveryMagic,
+
+    /// header revision: 1 targets a device, 2 a platform and board [default: 1, or 2 with --platform]
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..=2))]
+    header_rev: Option<u32>,
+
+    /// target device for header rev 1: name, alias or code, hex (0x...) or decimal
+    #[arg(
+      short,
+      long,
+      value_parser = parse_device,
+      required_unless_present = "platform",
+      required_if_eq("header_rev", "1"),
+      conflicts_with = "platform"
+    )]
+    device: Option<Device>,
+
+    /// target platform for header rev 2: name (e.g. bellatrix) or code, hex (0x...) or decimal
+    #[arg(short, long, value_parser = parse_platform, required_if_eq("header_rev", "2"))]
+    platform: Option<Platform>,
+
+    /// target board code for header rev 2, hex (0x...) or decimal [default: 0]
+    #[arg(short, long, value_parser = parse_u32, requires = "platform", conflicts_with = "device")]
+    board: Option<u32>,
+
+    /// OTA revision the image brings the device to (header rev 2 only)
+    #[arg(short, long, requires = "platform", conflicts_with = "device")]
+    target_rev: Option<u64>,
+
+    /// value of the magic 1 header field
+    #[arg(long, value_parser = parse_u32, default_value = "0")]
+    magic1: u32,
+
+    /// value of the magic 2 header field
+    #[arg(long, value_parser = parse_u32, default_value = "0")]
+    magic2: u32,
+
+    /// value of the minor header field
+    #[arg(long, value_parser = parse_u32, default_value = "0")]
+    minor: u32,
+  },
+
+  /// build a recovery V2 (FB03) fullbin firmware file for several devices
+  Recovery2 {
+    /// payload to package
+    input_file: PathBuf,
+
+    /// output file for the firmware [default: stdout]
+    output_file: Option<PathBuf>,
+
+    /// target device: name, alias (e.g. kpw5), group (e.g. all-kpw5) or code,
+    /// hex (0x...) or decimal; may be repeated up to 255 devices in total
+    #[arg(short, long = "device", value_parser = parse_device_group, required = true)]
+    devices: Vec<DeviceGroup>,
+
+    /// target platform: name (e.g. bellatrix) or code, hex (0x...) or decimal
+    #[arg(short, long, value_parser = parse_platform)]
+    platform: Platform,
+
+    /// target board code, hex (0x...) or decimal
+    #[arg(short, long, value_parser = parse_u32, default_value = "0")]
+    board: u32,
+
+    /// OTA revision the image brings the device to
+    #[arg(short, long, default_value_t = 0)]
+    target_rev: u64,
+
+    /// value of the magic 1 header field
+    #[arg(long, value_parser = parse_u32, default_value = "0")]
+    magic1: u32,
+
+    /// value of the magic 2 header field
+    #[arg(long, value_parser = parse_u32, default_value = "0")]
+    magic2: u32,
+
+    /// value of the minor header field
+    #[arg(long, value_parser = parse_u32, default_value = "0")]
+    minor: u32,
   },
 }
 
-fn get_input(path: Option<&PathBuf>) -> Result<Box<dyn Read>, Box<dyn std::error::Error>> {
+// the devices a single --device argument expands to
+#[derive(Clone, Debug)]
+struct DeviceGroup(Vec<Device>);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Format {
+  /// human-readable text
+  Text,
+  /// machine-readable json
+  Json,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum EntryFilter {
+  /// update scripts (*.ffs, *.sh)
+  Scripts,
+  /// regular files other than update scripts
+  Data,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum OtaMagic {
+  /// OTA [ota]
+  Fc02,
+  /// Versionless [vls]
+  Fd03,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum RecoveryMagic {
+  /// Fullbin
+  Fb01,
+  /// Fullbin
+  Fb02,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Ota2Magic {
+  /// OTA [ota]
+  Fc04,
+  /// Versionless [vls]
+  Fd04,
+}
+
+fn parse_code(s: &str) -> Result<u64, String> {
+  kindle_tool::parse_code(s).map_err(|e| format!("invalid code '{}': {}", s, e))
+}
+
+fn parse_u32(s: &str) -> Result<u32, String> {
+  let code = parse_code(s)?;
+  u32::try_from(code).map_err(|_| format!("'{}' does not fit in 32 bits", s))
+}
+
+// more threads than cpus only adds chunk buffers, so counts are capped there
+fn parse_jobs(s: &str) -> Result<usize, String> {
+  let jobs: usize = s.parse().map_err(|e| format!("invalid job count '{}': {}", s, e))?;
+  let cpus = std::thread::available_parallelism().map_or(1, |n| n.get());
+  if jobs == 0 {
+    return Ok(cpus);
+  }
+  Ok(jobs.min(cpus))
+}
+
+fn parse_device(s: &str) -> Result<Device, String> {
+  s.parse().map_err(|e: Error| e.to_string())
+}
+
+fn parse_device_group(s: &str) -> Result<DeviceGroup, String> {
+  Device::parse_group(s).map(DeviceGroup).map_err(|e| e.to_string())
+}
+
+fn parse_platform(s: &str) -> Result<Platform, String> {
+  s.parse().map_err(|e: Error| e.to_string())
+}
+
+fn get_input(path: Option<&PathBuf>) -> io::Result<Box<dyn Read>> {
   let reader: Box<dyn Read> = if let Some(p) = path {
     Box::new(File::open(p)?)
   } else {
@@ -60,8 +422,40 @@ fn get_input(path: Option<&PathBuf>) -> Result<Box<dyn Read>, Box<dyn std::error
   Ok(reader)
 }
 
-fn get_output(path: Option<&PathBuf>) -> Result<Box<dyn Write>, Box<dyn std::error::Error>> {
-  let writer: Box<dyn Write> = if let Some(p) = path {
+fn map_file(path: &Path) -> io::Result<Mmap> {
+  let file = File::open(path)?;
+  // SAFETY: firmware files are only ever read, and are not expected to
+  // change while we look at them
+  unsafe { Mmap::map(&file) }
+}
+
+// `<INPUT_FILE>.<ext>`: appending rather than replacing the extension keeps
+// it from ever naming the input itself
+fn default_dump_path(in_path: &Path, format: PayloadFormat) -> PathBuf {
+  let mut path = in_path.as_os_str().to_owned();
+  path.push(".");
+  path.push(format.extension());
+  PathBuf::from(path)
+}
+
+// creating the output truncates it, so it must not be the file being read
+fn check_not_input(in_path: &Path, out_path: Option<&PathBuf>) -> io::Result<()> {
+  let Some(out_path) = out_path else {
+    return Ok(());
+  };
+  // an output that doesn't exist yet can't be the input
+  let (Ok(input), Ok(output)) = (in_path.canonicalize(), out_path.canonicalize()) else {
+    return Ok(());
+  };
+  if input == output {
+    let msg = format!("refusing to overwrite the input file '{}'", in_path.display());
+    return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+  }
+  Ok(())
+}
+
+fn get_output(path: Option<&PathBuf>) -> io::Result<Box<dyn Write + Send>> {
+  let writer: Box<dyn Write + Send> = if let Some(p) = path {
     Box::new(File::create(p)?)
   } else {
     Box::new(io::stdout())
@@ -74,39 +468,178 @@ fn main() {
 
   let result = match cli.command {
     Commands::Inspect {
-      input_file
-    } => run_inspect(&input_file),
+      input_file,
+      verify,
+      format,
+    } => run_inspect(&input_file, verify, format),
     Commands::Dump {
       input_file,
       output_file,
-    } => run_dump(&input_file, output_file.as_ref()),
+      jobs,
+    } => run_dump(&input_file, output_file.as_ref(), jobs),
+    Commands::Extract {
+      input_file,
+      output_dir,
+      no_sigs,
+    } => run_extract(&input_file, &output_dir, no_sigs),
+    Commands::Ls {
+      input_file,
+      only,
+    } => run_ls(&input_file, only),
     Commands::Dm {
       input_file,
       output_file,
-    } => run_demangle(input_file.as_ref(), output_file.as_ref()),
+      jobs,
+    } => run_demangle(input_file.as_ref(), output_file.as_ref(), jobs),
     Commands::Md {
       input_file,
       output_file,
-    } => run_mangle(input_file.as_ref(), output_file.as_ref()),
+      jobs,
+    } => run_mangle(input_file.as_ref(), output_file.as_ref(), jobs),
+    Commands::Sign {
+      input_file,
+      output_file,
+      key,
+      cert_num,
+    } => run_sign(&input_file, output_file.as_ref(), &key, cert_num),
+    Commands::Verify {
+      input_file,
+      pubkeys,
+    } => run_verify(&input_file, &pubkeys),
+    Commands::Unwrap {
+      input_file,
+      output_file,
+      signature,
+      all,
+    } => run_unwrap(&input_file, &output_file, signature.as_ref(), all),
+    Commands::Info {
+      serial,
+      verify,
+      format,
+    } => {
+      let path = Path::new(&serial);
+      if path.is_file() {
+        run_inspect(path, verify, format)
+      } else {
+        run_info(&serial, format)
+      }
+    }
+    Commands::Devices {
+      name,
+      platform,
+      code,
+      format,
+    } => run_devices(name.as_deref(), platform, code, format),
+    Commands::Create {
+      bundle
+    } => run_create(bundle),
   };
 
   if let Err(e) = result {
     eprintln!("error: {}", e);
-    process::exit(1);
+    process::exit(exit_code(&e));
+  }
+}
+
+// 2 is taken by clap for usage errors
+fn exit_code(err: &Error) -> i32 {
+  match err {
+    Error::Io(_) => 3,
+    Error::UnknownMagic(_) => 4,
+    Error::TruncatedHeader { .. } => 5,
+    Error::InvalidMd5Encoding(_) | Error::Parse(_) => 6,
+    Error::Md5Mismatch { .. } => 7,
+    Error::SignatureSizeMismatch { .. }
+    | Error::NotSigned(_)
+    | Error::InvalidKey(_)
+    | Error::Rsa(_)
+    | Error::BadSignature(_) => 8,
+    Error::InvalidHeader(_)
+    | Error::UnsupportedBundle(_)
+    | Error::UnknownDevice(_)
+    | Error::AmbiguousDevice(_)
+    | Error::UnknownPlatform(_) => 9,
+    Error::PathTraversal(_) => 10,
+    Error::UnknownSerial(_) => 11,
   }
 }
 
-fn run_inspect(path: &PathBuf) -> Result<(), Box<dyn std::error::Error>> {
-  let mut file = File::open(path)?;
-  let bundle: UpdateBundle = file.read_le()?;
-  println!("{}", bundle);
+fn run_inspect(path: &Path, verify: bool, format: Format) -> Result<(), Error> {
+  let mmap = map_file(path)?;
+  let view = UpdateBundle::parse_bytes(&mmap)?;
+  let bundle = &view.bundle;
+
+  if let UpdateBundle::RecoveryV2Fb03(header) = bundle.innermost() {
+    let platform = Platform::from_repr(header.platform_code as usize).unwrap_or_default();
+    for device in header.foreign_devices() {
+      let device_platform = device.platform().unwrap_or_default();
+      eprintln!("warning: {} (0x{:04X}) is a {} device, not {}", device, device as u16, device_platform, platform);
+    }
+  }
+
+  let payload_format = if matches!(bundle.innermost(), UpdateBundle::Unknown { .. }) {
+    None
+  } else {
+    Some(view.payload_format()?)
+  };
+
+  let md5 = if verify { Some(view.verify_payload_md5()?) } else { None };
+
+  match format {
+    Format::Text => {
+      println!("{}", bundle);
+      if let Some(payload_format) = payload_format {
+        println!("\n{:<14} {}", "Payload:", payload_format);
+      }
+      if let Some(md5) = &md5 {
+        let verdict = if md5.is_match() { "match" } else { "MISMATCH" };
+        println!("\n{:<14} {} ({})", "Payload MD5:", md5.actual, verdict);
+      }
+    }
+    Format::Json => {
+      let mut json = serde_json::to_value(bundle).map_err(io::Error::from)?;
+      if let Some(payload_format) = payload_format {
+        json["payload_format"] = serde_json::to_value(payload_format).map_err(io::Error::from)?;
+      }
+      if let Some(md5) = &md5 {
+        let mut check = serde_json::to_value(md5).map_err(io::Error::from)?;
+        check["match"] = md5.is_match().into();
+        json["payload_md5"] = check;
+      }
+      println!("{}", serde_json::to_string_pretty(&json).map_err(io::Error::from)?);
+    }
+  }
+
+  if let Some(md5) = md5
+    && !md5.is_match()
+  {
+    return Err(Error::Md5Mismatch {
+      expected: md5.expected,
+      actual: md5.actual,
+    });
+  }
   Ok(())
 }
 
-fn run_dump(in_path: &PathBuf, out_path: Option<&PathBuf>) -> Result<(), Box<dyn std::error::Error>> {
-  let mut in_file = File::open(in_path)?;
-  let writer = get_output(out_path)?;
-  let mut buf_writer = BufWriter::new(writer);
+fn run_dump(in_path: &Path, out_path: Option<&PathBuf>, jobs: usize) -> Result<(), Error> {
+  let mmap = map_file(in_path)?;
+  #[cfg(unix)]
+  mmap.advise(Advice::Sequential)?;
+  let view = UpdateBundle::parse_bytes(&mmap)?;
+
+  // never spill binary onto a terminal: fall back to a file named after the payload format
+  let default_path;
+  let out_path = match out_path {
+    None if io::stdout().is_terminal() => {
+      default_path = default_dump_path(in_path, view.payload_format()?);
+      Some(&default_path)
+    }
+    _ => out_path,
+  };
+  check_not_input(in_path, out_path)?;
+
+  // unbuffered, so the 1 MiB chunks of the view reach the file as they are
+  let mut writer = get_output(out_path)?;
 
   if let Some(path) = out_path {
     eprintln!("extracting payload from '{}' to '{}'...", in_path.display(), path.display());
@@ -114,50 +647,311 @@ fn run_dump(in_path: &PathBuf, out_path: Option<&PathBuf>) -> Result<(), Box<dyn
     eprintln!("extracting payload from '{}' to stdout...", in_path.display());
   };
 
-  kindle_tool::dump_payload(&mut in_file, &mut buf_writer)?;
+  view.dump_payload(&mut writer, jobs)?;
   Ok(())
 }
 
-fn run_demangle(in_path: Option<&PathBuf>, out_path: Option<&PathBuf>) -> Result<(), Box<dyn std::error::Error>> {
-  let reader = get_input(in_path)?;
-  let mut buf_reader = BufReader::new(reader);
-  let writer = get_output(out_path)?;
-  let mut buf_writer = BufWriter::new(writer);
+fn run_extract(in_path: &PathBuf, out_dir: &Path, skip_signatures: bool) -> Result<(), Error> {
+  let mut in_file = BufReader::new(File::open(in_path)?);
+
+  eprintln!("extracting payload from '{}' to '{}'...", in_path.display(), out_dir.display());
+
+  let extracted = kindle_tool::extract_payload(&mut in_file, out_dir, skip_signatures)?;
+  eprintln!("extracted {} entries", extracted.len());
+  Ok(())
+}
+
+fn run_ls(path: &PathBuf, only: Option<EntryFilter>) -> Result<(), Error> {
+  let mut file = BufReader::new(File::open(path)?);
+  let entries = kindle_tool::list_payload(&mut file)?;
+
+  let entries = entries.iter().filter(|e| match only {
+    Some(EntryFilter::Scripts) => e.is_script(),
+    Some(EntryFilter::Data) => e.is_data(),
+    None => true,
+  });
+  for entry in entries {
+    println!("{}", entry);
+  }
+  Ok(())
+}
+
+fn run_demangle(in_path: Option<&PathBuf>, out_path: Option<&PathBuf>, jobs: usize) -> Result<(), Error> {
+  let mut reader = get_input(in_path)?;
+  let mut writer = get_output(out_path)?;
 
   eprintln!("deobfuscating stream...");
 
-  const BUFFER_SIZE: usize = 8192;
-  let mut buffer = [0; BUFFER_SIZE];
-  loop {
-    let bytes_read = buf_reader.read(&mut buffer)?;
-    if bytes_read == 0 {
-      break;
+  kindle_tool::deobfuscate_stream(&mut reader, &mut writer, jobs)?;
+  Ok(())
+}
+
+fn run_mangle(in_path: Option<&PathBuf>, out_path: Option<&PathBuf>, jobs: usize) -> Result<(), Error> {
+  let mut reader = get_input(in_path)?;
+  let mut writer = get_output(out_path)?;
+
+  eprintln!("obfuscating stream...");
+
+  kindle_tool::obfuscate_stream(&mut reader, &mut writer, jobs)?;
+  Ok(())
+}
+
+fn run_sign(
+  in_path: &PathBuf,
+  out_path: Option<&PathBuf>,
+  key_path: &PathBuf,
+  cert_num: u32,
+) -> Result<(), Error> {
+  let key = kindle_tool::parse_private_key(&std::fs::read_to_string(key_path)?)?;
+  check_not_input(in_path, out_path)?;
+  let mut in_file = BufReader::new(File::open(in_path)?);
+
+  if let Some(path) = out_path {
+    eprintln!("signing '{}' with cert {} to '{}'...", in_path.display(), cert_num, path.display());
+  } else {
+    eprintln!("signing '{}' with cert {} to stdout...", in_path.display(), cert_num);
+  };
+
+  // the output is only created once the key and bundle have checked out
+  let signature = kindle_tool::bundle_signature(&key, cert_num, &mut in_file)?;
+  let mut buf_writer = BufWriter::new(get_output(out_path)?);
+  kindle_tool::write_signed_bundle(&signature, &mut in_file, &mut buf_writer)?;
+  buf_writer.flush()?;
+  Ok(())
+}
+
+fn run_verify(path: &PathBuf, key_paths: &[PathBuf]) -> Result<(), Error> {
+  let keys = key_paths
+    .iter()
+    .map(|p| kindle_tool::parse_public_key(&std::fs::read_to_string(p)?))
+    .collect::<Result<Vec<_>, _>>()?;
+  let mut file = BufReader::new(File::open(path)?);
+
+  let status = kindle_tool::verify_signature(&keys, &mut file)?;
+  println!("{:<14} {}", "Signature:", status);
+  if status != SignatureStatus::Valid {
+    return Err(Error::BadSignature(status));
+  }
+  Ok(())
+}
+
+fn run_unwrap(in_path: &PathBuf, out_path: &PathBuf, sig_path: Option<&PathBuf>, all: bool) -> Result<(), Error> {
+  let mut in_file = BufReader::new(File::open(in_path)?);
+  let mut buf_writer = BufWriter::new(File::create(out_path)?);
+
+  eprintln!("unwrapping '{}' to '{}'...", in_path.display(), out_path.display());
+
+  let signatures = kindle_tool::unwrap_bundle(&mut in_file, &mut buf_writer, all)?;
+  buf_writer.flush()?;
+
+  let sig_path = sig_path.cloned().unwrap_or_else(|| {
+    let mut path = out_path.clone().into_os_string();
+    path.push(".sig");
+    PathBuf::from(path)
+  });
+  for (layer, signature) in signatures.iter().enumerate() {
+    let mut path = sig_path.clone().into_os_string();
+    if layer > 0 {
+      path.push(format!(".{}", layer));
     }
-    let chunk = &mut buffer[..bytes_read];
-    kindle_tool::deobfuscate_in_place(chunk);
-    buf_writer.write_all(chunk)?;
+    let path = PathBuf::from(path);
+    std::fs::write(&path, &signature.signature)?;
+    eprintln!("wrote cert {} signature to '{}'", signature.cert_num, path.display());
   }
   Ok(())
 }
 
-fn run_mangle(in_path: Option<&PathBuf>, out_path: Option<&PathBuf>) -> Result<(), Box<dyn std::error::Error>> {
-  let reader = get_input(in_path)?;
-  let mut buf_reader = BufReader::new(reader);
-  let writer = get_output(out_path)?;
-  let mut buf_writer = BufWriter::new(writer);
+fn run_info(serial: &str, format: Format) -> Result<(), Error> {
+  let device = Device::from_serial(serial).ok_or_else(|| Error::UnknownSerial(serial.to_string()))?;
 
-  eprintln!("obfuscating stream...");
+  match format {
+    Format::Text => {
+      println!("{:<14} {}", "Serial:", serial);
+      println!("{:<14} {} (0x{:04X})", "Device:", device, device as u16);
+      if let Some(platform) = device.platform() {
+        println!("{:<14} {}", "Platform:", platform);
+      }
+      println!("{:<14} {}", "Root PW:", kindle_tool::root_password(serial, PasswordScheme::Legacy));
+      println!("{:<14} {}", "Root PW (5.x):", kindle_tool::root_password(serial, PasswordScheme::Fw5));
+    }
+    Format::Json => {
+      let json = serde_json::json!({
+        "serial": serial,
+        "device": { "code": device as u16, "name": device.to_string() },
+        "platform": device.platform().map(|p| serde_json::json!({ "code": p as u32, "name": p.to_string() })),
+        "root_password": {
+          "legacy": kindle_tool::root_password(serial, PasswordScheme::Legacy),
+          "fw5": kindle_tool::root_password(serial, PasswordScheme::Fw5),
+        },
+      });
+      println!("{}", serde_json::to_string_pretty(&json).map_err(io::Error::from)?);
+    }
+  }
+  Ok(())
+}
+
+fn run_devices(name: Option<&str>, platform: Option<Platform>, code: Option<u64>, format: Format) -> Result<(), Error> {
+  let name = name.map(str::to_lowercase);
+  let devices = Device::known().filter(|&d| {
+    name.as_ref().is_none_or(|n| d.to_string().to_lowercase().contains(n))
+      && platform.is_none_or(|p| d.platform() == Some(p))
+      && code.is_none_or(|c| c == d as u64)
+  });
 
-  const BUFFER_SIZE: usize = 8192;
-  let mut buffer = [0; BUFFER_SIZE];
-  loop {
-    let bytes_read = buf_reader.read(&mut buffer)?;
-    if bytes_read == 0 {
-      break;
+  match format {
+    Format::Text => {
+      let mut out = io::stdout().lock();
+      writeln!(out, "{:<6}  {:<6}  {:<20}  NAME", "CODE", "SERIAL", "PLATFORM")?;
+      for device in devices {
+        let platform = device.platform().map_or_else(|| "-".to_string(), |p| p.to_string());
+        writeln!(out, "0x{:04X}  {:<6}  {:<20}  {}", device as u16, device.serial_prefix(), platform, device)?;
+      }
+    }
+    Format::Json => {
+      let rows: Vec<_> = devices
+        .map(|device| {
+          serde_json::json!({
+            "name": device.to_string(),
+            "code": device as u16,
+            "serial_prefix": device.serial_prefix(),
+            "platform": device.platform().map(|p| serde_json::json!({ "code": p as u32, "name": p.to_string() })),
+          })
+        })
+        .collect();
+      println!("{}", serde_json::to_string_pretty(&rows).map_err(io::Error::from)?);
     }
-    let chunk = &mut buffer[..bytes_read];
-    kindle_tool::obfuscate_in_place(chunk);
-    buf_writer.write_all(chunk)?;
   }
   Ok(())
-}
\ No newline at end of file
+}
+
+fn run_create(command: CreateCommands) -> Result<(), Error> {
+  let (in_path, out_path, bundle) = match command {
+    CreateCommands::Ota {
+      input_file,
+      output_file,
+      magic,
+      device,
+      source_rev,
+      target_rev,
+      optional,
+    } => {
+      let header = OtaV1::new(source_rev, target_rev, device, optional)?;
+      let bundle = match magic {
+        OtaMagic::Fc02 => UpdateBundle::OtaV1Fc02(header),
+        OtaMagic::Fd03 => UpdateBundle::OtaV1Fd03(header),
+      };
+      (input_file, output_file, bundle)
+    }
+    CreateCommands::Ota2 {
+      input_file,
+      output_file,
+      magic,
+      devices,
+      source_rev,
+      target_rev,
+      critical,
+      metadata,
+    } => {
+      let devices: Vec<Device> = devices.into_iter().flat_map(|g| g.0).collect();
+      let header = OtaV2::new(source_rev, target_rev, &devices, critical, metadata)?;
+      let bundle = match magic {
+        Ota2Magic::Fc04 => UpdateBundle::OtaV2Fc04(header),
+        Ota2Magic::Fd04 => UpdateBundle::OtaV2Fd04(header),
+      };
+      (input_file, output_file, bundle)
+    }
+    CreateCommands::Recovery {
+      input_file,
+      output_file,
+      magic,
+      header_rev: _,
+      device,
+      platform,
+      board,
+      target_rev,
+      magic1,
+      magic2,
+      minor,
+    } => {
+      // clap has already matched the target to the header revision
+      let device_info = match (device, platform) {
+        (Some(device), _) => RecoveryDevice::Device(device, device as u16),
+        (None, Some(platform)) => RecoveryDevice::Platform {
+          platform,
+          platform_code: platform as u32,
+          board: board.unwrap_or_default(),
+        },
+        (None, None) => unreachable!("clap requires --device or --platform"),
+      };
+      let header = RecoveryV1::new(magic1, magic2, minor, device_info, target_rev)?;
+      let bundle = match magic {
+        RecoveryMagic::Fb01 => UpdateBundle::RecoveryV1Fb01(header),
+        RecoveryMagic::Fb02 => UpdateBundle::RecoveryV1Fb02(header),
+      };
+      (input_file, output_file, bundle)
+    }
+    CreateCommands::Recovery2 {
+      input_file,
+      output_file,
+      devices,
+      platform,
+      board,
+      target_rev,
+      magic1,
+      magic2,
+      minor,
+    } => {
+      let devices: Vec<Device> = devices.into_iter().flat_map(|g| g.0).collect();
+      let header = RecoveryV2::new(target_rev, magic1, magic2, minor, platform, board, &devices)?;
+      (input_file, output_file, UpdateBundle::RecoveryV2Fb03(header))
+    }
+  };
+
+  let mut in_file = BufReader::new(File::open(&in_path)?);
+  let writer = get_output(out_path.as_ref())?;
+  let mut buf_writer = BufWriter::new(writer);
+
+  if let Some(path) = &out_path {
+    eprintln!("creating {} bundle from '{}' to '{}'...", bundle.magic_str(), in_path.display(), path.display());
+  } else {
+    eprintln!("creating {} bundle from '{}' to stdout...", bundle.magic_str(), in_path.display());
+  };
+
+  kindle_tool::create_bundle(bundle, &mut in_file, &mut buf_writer)?;
+  buf_writer.flush()?;
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn default_dump_path_appends_the_extension() {
+    let cases = [
+      ("fw.bin", PayloadFormat::Gzip, "fw.bin.tar.gz"),
+      ("fw.dat", PayloadFormat::Unknown, "fw.dat.dat"),
+      ("x.img", PayloadFormat::Ext3, "x.img.img"),
+      ("fw.tar.gz", PayloadFormat::Tar, "fw.tar.gz.tar"),
+      ("update", PayloadFormat::Xz, "update.xz"),
+    ];
+    for (input, format, expected) in cases {
+      assert_eq!(default_dump_path(Path::new(input), format), Path::new(expected));
+    }
+  }
+
+  #[test]
+  fn output_must_not_be_the_input() {
+    let dir = std::env::temp_dir().join(format!("kindle-tool-dump-{}", process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("fw.dat");
+    std::fs::write(&input, b"bundle").unwrap();
+
+    assert!(check_not_input(&input, Some(&input)).is_err());
+    assert!(check_not_input(&input, Some(&dir.join(".").join("fw.dat"))).is_err());
+    assert!(check_not_input(&input, Some(&default_dump_path(&input, PayloadFormat::Unknown))).is_ok());
+    assert!(check_not_input(&input, None).is_ok());
+    assert_eq!(std::fs::read(&input).unwrap(), b"bundle");
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+}
@@ -0,0 +1,449 @@
+use std::collections::BTreeMap;
+use std::io::{BufWriter, ErrorKind, Read, Result as IoResult, Seek, SeekFrom, Write};
+use std::sync::mpsc::{self, TryRecvError};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use strum::Display as StrumDisplay;
+
+// both directions swap the nibbles of each byte and xor with a fixed key
+const DEOBFUSCATE_KEY: u8 = 0xA7;
+const OBFUSCATE_KEY: u8 = 0x7A;
+
+/// an implementation of the nibble-swap-and-xor transform
+#[derive(Debug, Clone, Copy, PartialEq, Eq, StrumDisplay)]
+pub enum Kernel {
+  #[strum(to_string = "scalar")]
+  Scalar,
+  #[cfg(target_arch = "x86_64")]
+  #[strum(to_string = "sse2")]
+  Sse2,
+  #[cfg(target_arch = "x86_64")]
+  #[strum(to_string = "avx2")]
+  Avx2,
+  #[cfg(target_arch = "aarch64")]
+  #[strum(to_string = "neon")]
+  Neon,
+}
+
+impl Kernel {
+  /// the fastest kernel the running cpu supports
+  pub fn detect() -> Kernel {
+    static DETECTED: OnceLock<Kernel> = OnceLock::new();
+    *DETECTED.get_or_init(|| Kernel::available().pop().unwrap_or(Kernel::Scalar))
+  }
+
+  /// every kernel the running cpu supports, slowest first
+  pub fn available() -> Vec<Kernel> {
+    let mut kernels = vec![Kernel::Scalar];
+    #[cfg(target_arch = "x86_64")]
+    {
+      // sse2 is part of the x86_64 baseline
+      kernels.push(Kernel::Sse2);
+      if std::arch::is_x86_feature_detected!("avx2") {
+        kernels.push(Kernel::Avx2);
+      }
+    }
+    #[cfg(target_arch = "aarch64")]
+    if std::arch::is_aarch64_feature_detected!("neon") {
+      kernels.push(Kernel::Neon);
+    }
+    kernels
+  }
+
+  pub fn deobfuscate(self, data: &mut [u8]) {
+    self.apply(data, DEOBFUSCATE_KEY);
+  }
+
+  pub fn obfuscate(self, data: &mut [u8]) {
+    self.apply(data, OBFUSCATE_KEY);
+  }
+
+  // kernels the cpu lacks fall back to scalar rather than fault
+  fn apply(self, data: &mut [u8], key: u8) {
+    match self {
+      Kernel::Scalar => scalar(data, key),
+      #[cfg(target_arch = "x86_64")]
+      // SAFETY: sse2 is part of the x86_64 baseline
+      Kernel::Sse2 => unsafe { x86::sse2(data, key) },
+      #[cfg(target_arch = "x86_64")]
+      Kernel::Avx2 => {
+        if std::arch::is_x86_feature_detected!("avx2") {
+          // SAFETY: avx2 support was just checked
+          unsafe { x86::avx2(data, key) }
+        } else {
+          scalar(data, key)
+        }
+      }
+      #[cfg(target_arch = "aarch64")]
+      Kernel::Neon => {
+        if std::arch::is_aarch64_feature_detected!("neon") {
+          // SAFETY: neon support was just checked
+          unsafe { arm::neon(data, key) }
+        } else {
+          scalar(data, key)
+        }
+      }
+    }
+  }
+}
+
+// a LUT would be ~7x slower (benchmarked on aarch64)
+fn scalar(data: &mut [u8], key: u8) {
+  for byte in data.iter_mut() {
+    *byte = byte.rotate_left(4) ^ key;
+  }
+}
+
+pub fn deobfuscate_in_place(data: &mut [u8]) {
+  Kernel::detect().deobfuscate(data);
+}
+
+pub fn obfuscate_in_place(data: &mut [u8]) {
+  Kernel::detect().obfuscate(data);
+}
+
+/// a reader that deobfuscates the bytes read from the inner reader, e.g. to
+/// feed a bundle payload straight into a decompressor
+#[derive(Debug)]
+pub struct DeobfuscatingReader<R> {
+  inner: R,
+}
+
+impl<R> DeobfuscatingReader<R> {
+  pub fn new(inner: R) -> Self {
+    Self { inner }
+  }
+
+  pub fn get_ref(&self) -> &R {
+    &self.inner
+  }
+
+  pub fn into_inner(self) -> R {
+    self.inner
+  }
+}
+
+impl<R: Read> Read for DeobfuscatingReader<R> {
+  fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+    let bytes_read = self.inner.read(buf)?;
+    deobfuscate_in_place(&mut buf[..bytes_read]);
+    Ok(bytes_read)
+  }
+}
+
+// the transform works byte by byte, so positions carry over unchanged
+impl<R: Seek> Seek for DeobfuscatingReader<R> {
+  fn seek(&mut self, pos: SeekFrom) -> IoResult<u64> {
+    self.inner.seek(pos)
+  }
+}
+
+/// a writer that obfuscates the bytes written to it before passing them on
+/// to the inner writer
+#[derive(Debug)]
+pub struct ObfuscatingWriter<W> {
+  inner: W,
+  scratch: Vec<u8>,
+}
+
+impl<W> ObfuscatingWriter<W> {
+  pub fn new(inner: W) -> Self {
+    Self { inner, scratch: Vec::new() }
+  }
+
+  pub fn get_ref(&self) -> &W {
+    &self.inner
+  }
+
+  pub fn into_inner(self) -> W {
+    self.inner
+  }
+}
+
+impl<W: Write> Write for ObfuscatingWriter<W> {
+  // nothing is held back: each call writes out everything it accepts
+  fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+    let len = buf.len().min(CHUNK_SIZE);
+    self.scratch.clear();
+    self.scratch.extend_from_slice(&buf[..len]);
+    obfuscate_in_place(&mut self.scratch);
+    self.inner.write_all(&self.scratch)?;
+    Ok(len)
+  }
+
+  fn flush(&mut self) -> IoResult<()> {
+    self.inner.flush()
+  }
+}
+
+impl<W: Seek> Seek for ObfuscatingWriter<W> {
+  fn seek(&mut self, pos: SeekFrom) -> IoResult<u64> {
+    self.inner.seek(pos)
+  }
+}
+
+/// the size of the chunks streams are transformed in, a multiple of the page size
+pub const CHUNK_SIZE: usize = 1024 * 1024;
+
+// fills `buffer` unless the reader runs dry first, returning the bytes read
+fn read_chunk<R: Read>(reader: &mut R, buffer: &mut [u8]) -> IoResult<usize> {
+  let mut filled = 0;
+  while filled < buffer.len() {
+    match reader.read(&mut buffer[filled..]) {
+      Ok(0) => break,
+      Ok(n) => filled += n,
+      Err(e) if e.kind() == ErrorKind::Interrupted => {}
+      Err(e) => return Err(e),
+    }
+  }
+  Ok(filled)
+}
+
+// reads chunks on the calling thread, transforms them on `jobs` workers and
+// writes them back in order from another thread. the chunk buffers are
+// recycled through `free` and only allocated while none is free, up to two
+// per job, so short streams stay small however many jobs are asked for
+fn transform_stream<R: Read, W: Write + Send>(
+  reader: &mut R,
+  writer: &mut W,
+  transform: fn(&mut [u8]),
+  jobs: usize,
+) -> IoResult<()> {
+  let (free_tx, free_rx) = mpsc::channel::<Vec<u8>>();
+  let mut allocated = 0;
+  let (work_tx, work_rx) = mpsc::channel::<(usize, Vec<u8>)>();
+  let work_rx = Mutex::new(work_rx);
+  let (done_tx, done_rx) = mpsc::channel::<(usize, Vec<u8>)>();
+
+  thread::scope(|scope| {
+    for _ in 0..jobs {
+      let done_tx = done_tx.clone();
+      let work_rx = &work_rx;
+      scope.spawn(move || {
+        loop {
+          let next = work_rx.lock().expect("no worker panics while holding the lock").recv();
+          let Ok((index, mut chunk)) = next else { break };
+          transform(&mut chunk);
+          if done_tx.send((index, chunk)).is_err() {
+            break;
+          }
+        }
+      });
+    }
+    drop(done_tx);
+
+    let writer_thread = scope.spawn(move || -> IoResult<()> {
+      let mut pending = BTreeMap::new();
+      let mut next = 0;
+      for (index, chunk) in done_rx {
+        pending.insert(index, chunk);
+        while let Some(chunk) = pending.remove(&next) {
+          writer.write_all(&chunk)?;
+          next += 1;
+          // the reader may be gone already, which is fine
+          let _ = free_tx.send(chunk);
+        }
+      }
+      Ok(())
+    });
+
+    let mut read_result = Ok(());
+    let mut index = 0;
+    loop {
+      let mut chunk = match free_rx.try_recv() {
+        Ok(chunk) => chunk,
+        // a failed writer drops `free_tx`, which ends the loop
+        Err(TryRecvError::Disconnected) => break,
+        Err(TryRecvError::Empty) if allocated < jobs * 2 => {
+          allocated += 1;
+          Vec::new()
+        }
+        Err(TryRecvError::Empty) => match free_rx.recv() {
+          Ok(chunk) => chunk,
+          Err(_) => break,
+        },
+      };
+      chunk.resize(CHUNK_SIZE, 0);
+      match read_chunk(reader, &mut chunk) {
+        Ok(0) => break,
+        Ok(bytes_read) => {
+          chunk.truncate(bytes_read);
+          if work_tx.send((index, chunk)).is_err() {
+            break;
+          }
+          index += 1;
+        }
+        Err(e) => {
+          read_result = Err(e);
+          break;
+        }
+      }
+    }
+    drop(work_tx);
+
+    let write_result = writer_thread.join().expect("writer thread panicked");
+    read_result.and(write_result)
+  })
+}
+
+/// deobfuscates everything read from `reader` into `writer` in chunks of
+/// [`CHUNK_SIZE`], spreading them over `jobs` threads while keeping them in
+/// order. `reader` may be a file or an unseekable stream such as stdin
+pub fn deobfuscate_stream<R: Read, W: Write + Send>(reader: &mut R, writer: &mut W, jobs: usize) -> IoResult<()> {
+  if jobs > 1 {
+    return transform_stream(reader, writer, deobfuscate_in_place, jobs);
+  }
+  let mut writer = BufWriter::with_capacity(CHUNK_SIZE, writer);
+  std::io::copy(&mut DeobfuscatingReader::new(reader), &mut writer)?;
+  writer.flush()
+}
+
+/// obfuscates everything read from `reader` into `writer` in chunks of
+/// [`CHUNK_SIZE`], spreading them over `jobs` threads while keeping them in order
+pub fn obfuscate_stream<R: Read, W: Write + Send>(reader: &mut R, writer: &mut W, jobs: usize) -> IoResult<()> {
+  if jobs > 1 {
+    return transform_stream(reader, writer, obfuscate_in_place, jobs);
+  }
+  let mut writer = BufWriter::with_capacity(CHUNK_SIZE, ObfuscatingWriter::new(writer));
+  std::io::copy(reader, &mut writer)?;
+  writer.flush()
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+  use std::arch::x86_64::*;
+
+  // there is no byte shift, so shift 16-bit lanes and mask off the bits
+  // that crossed into the neighbouring byte
+  #[target_feature(enable = "sse2")]
+  pub fn sse2(data: &mut [u8], key: u8) {
+    let (chunks, rest) = data.as_chunks_mut::<16>();
+    let low = _mm_set1_epi8(0x0F);
+    let key_v = _mm_set1_epi8(key as i8);
+    for chunk in chunks {
+      // SAFETY: the chunk is 16 bytes and the accesses are unaligned
+      let v = unsafe { _mm_loadu_si128(chunk.as_ptr().cast()) };
+      let swapped = _mm_or_si128(
+        _mm_and_si128(_mm_srli_epi16(v, 4), low),
+        _mm_andnot_si128(low, _mm_slli_epi16(v, 4)),
+      );
+      unsafe { _mm_storeu_si128(chunk.as_mut_ptr().cast(), _mm_xor_si128(swapped, key_v)) };
+    }
+    super::scalar(rest, key);
+  }
+
+  #[target_feature(enable = "avx2")]
+  pub fn avx2(data: &mut [u8], key: u8) {
+    let (chunks, rest) = data.as_chunks_mut::<32>();
+    let low = _mm256_set1_epi8(0x0F);
+    let key_v = _mm256_set1_epi8(key as i8);
+    for chunk in chunks {
+      // SAFETY: the chunk is 32 bytes and the accesses are unaligned
+      let v = unsafe { _mm256_loadu_si256(chunk.as_ptr().cast()) };
+      let swapped = _mm256_or_si256(
+        _mm256_and_si256(_mm256_srli_epi16(v, 4), low),
+        _mm256_andnot_si256(low, _mm256_slli_epi16(v, 4)),
+      );
+      unsafe { _mm256_storeu_si256(chunk.as_mut_ptr().cast(), _mm256_xor_si256(swapped, key_v)) };
+    }
+    sse2(rest, key);
+  }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod arm {
+  use std::arch::aarch64::*;
+
+  #[target_feature(enable = "neon")]
+  pub fn neon(data: &mut [u8], key: u8) {
+    let (chunks, rest) = data.as_chunks_mut::<16>();
+    let key_v = vdupq_n_u8(key);
+    for chunk in chunks {
+      // SAFETY: the chunk is 16 bytes
+      let v = unsafe { vld1q_u8(chunk.as_ptr()) };
+      // shift the high nibble down, then insert the low nibble above it
+      let swapped = vsliq_n_u8(vshrq_n_u8(v, 4), v, 4);
+      unsafe { vst1q_u8(chunk.as_mut_ptr(), veorq_u8(swapped, key_v)) };
+    }
+    super::scalar(rest, key);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // covers an empty buffer, a scalar-only tail and one past each vector width
+  const LENGTHS: &[usize] = &[0, 1, 15, 16, 31, 32, 33];
+
+  fn input(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 31 % 251) as u8).collect()
+  }
+
+  #[test]
+  fn kernels_agree_with_scalar() {
+    for kernel in Kernel::available() {
+      for &len in LENGTHS {
+        let mut expected = input(len);
+        Kernel::Scalar.deobfuscate(&mut expected);
+        let mut data = input(len);
+        kernel.deobfuscate(&mut data);
+        assert_eq!(data, expected, "{} deobfuscation of {} bytes", kernel, len);
+
+        let mut expected = input(len);
+        Kernel::Scalar.obfuscate(&mut expected);
+        let mut data = input(len);
+        kernel.obfuscate(&mut data);
+        assert_eq!(data, expected, "{} obfuscation of {} bytes", kernel, len);
+      }
+    }
+  }
+
+  #[test]
+  fn deobfuscate_undoes_obfuscate() {
+    for kernel in Kernel::available() {
+      for &len in LENGTHS {
+        let mut data = input(len);
+        kernel.obfuscate(&mut data);
+        kernel.deobfuscate(&mut data);
+        assert_eq!(data, input(len), "{} round trip of {} bytes", kernel, len);
+      }
+    }
+  }
+
+  // hands out at most a few bytes per read, like a pipe might
+  struct ShortReads<'a>(&'a [u8]);
+
+  impl Read for ShortReads<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+      let len = buf.len().min(self.0.len()).min(4093);
+      buf[..len].copy_from_slice(&self.0[..len]);
+      self.0 = &self.0[len..];
+      Ok(len)
+    }
+  }
+
+  #[test]
+  fn streams_keep_order_on_several_jobs() {
+    let data = input(3 * CHUNK_SIZE + 1234);
+    type Stream = fn(&mut ShortReads, &mut Vec<u8>, usize) -> IoResult<()>;
+    let streams: [Stream; 2] = [|r, w, jobs| deobfuscate_stream(r, w, jobs), |r, w, jobs| obfuscate_stream(r, w, jobs)];
+    for stream in streams {
+      let mut expected = Vec::new();
+      stream(&mut ShortReads(&data), &mut expected, 1).unwrap();
+      assert_eq!(expected.len(), data.len());
+      for jobs in [2, 3, 8] {
+        let mut output = Vec::new();
+        stream(&mut ShortReads(&data), &mut output, jobs).unwrap();
+        assert!(output == expected, "{} jobs", jobs);
+      }
+    }
+  }
+
+  #[test]
+  fn scalar_matches_known_bytes() {
+    let mut data = vec![0x00, 0x12, 0xA7, 0xFF];
+    Kernel::Scalar.deobfuscate(&mut data);
+    assert_eq!(data, [0xA7, 0x86, 0xDD, 0x58]);
+  }
+}
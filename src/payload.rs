@@ -0,0 +1,114 @@
+use crate::signing::ENVELOPE_HEADER_SIZE;
+use crate::{Error, SignatureEnvelope, UpdateBundle};
+use std::io::{Error as IoError, ErrorKind, Read, Result as IoResult, Seek, SeekFrom};
+use std::ops::Range;
+
+/// the still obfuscated payload of a bundle, as a window onto the reader
+/// holding it. positions are relative to the start of the payload, and reads
+/// stop at its end
+#[derive(Debug)]
+pub struct PayloadReader<R> {
+  inner: R,
+  range: Range<u64>,
+  pos: u64,
+}
+
+impl<R: Seek> PayloadReader<R> {
+  /// the payload occupying `range` of `inner`, e.g. from [`UpdateBundle::payload_range`]
+  pub fn new(mut inner: R, range: Range<u64>) -> IoResult<Self> {
+    inner.seek(SeekFrom::Start(range.start))?;
+    Ok(Self { inner, range, pos: 0 })
+  }
+}
+
+impl<R> PayloadReader<R> {
+  /// where the payload sits in the inner reader
+  pub fn range(&self) -> Range<u64> {
+    self.range.clone()
+  }
+
+  pub fn len(&self) -> u64 {
+    self.range.end.saturating_sub(self.range.start)
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  pub fn get_ref(&self) -> &R {
+    &self.inner
+  }
+
+  pub fn into_inner(self) -> R {
+    self.inner
+  }
+}
+
+impl<R: Read> Read for PayloadReader<R> {
+  fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+    let remaining = self.len().saturating_sub(self.pos);
+    let len = buf.len().min(remaining.try_into().unwrap_or(usize::MAX));
+    let bytes_read = self.inner.read(&mut buf[..len])?;
+    self.pos += bytes_read as u64;
+    Ok(bytes_read)
+  }
+}
+
+impl<R: Seek> Seek for PayloadReader<R> {
+  fn seek(&mut self, pos: SeekFrom) -> IoResult<u64> {
+    let (base, offset) = match pos {
+      SeekFrom::Start(offset) => (0, offset as i128),
+      SeekFrom::Current(offset) => (self.pos, offset as i128),
+      SeekFrom::End(offset) => (self.len(), offset as i128),
+    };
+    let Ok(pos) = u64::try_from(base as i128 + offset) else {
+      return Err(IoError::new(ErrorKind::InvalidInput, "seek before the start of the payload"));
+    };
+    // like a file, seeking past the end is allowed and reads nothing there
+    self.inner.seek(SeekFrom::Start(self.range.start + pos))?;
+    self.pos = pos;
+    Ok(pos)
+  }
+}
+
+impl SignatureEnvelope {
+  /// bytes from the SP01 magic to the wrapped bundle
+  pub fn envelope_len(&self) -> u64 {
+    ENVELOPE_HEADER_SIZE + self.signature.len() as u64
+  }
+}
+
+impl UpdateBundle {
+  /// parses a bundle header like [`UpdateBundle::read_from`] and hands back
+  /// the payload that follows it, up to the end of `reader`
+  pub fn read_with_payload<R: Read + Seek>(mut reader: R) -> Result<(Self, PayloadReader<R>), Error> {
+    let bundle = Self::read_from(&mut reader)?;
+    let start = reader.stream_position()?;
+    let end = reader.seek(SeekFrom::End(0))?;
+    let payload = PayloadReader::new(reader, start..end.max(start))?;
+    Ok((bundle, payload))
+  }
+
+  /// bytes from the magic to the payload, including any signature envelopes,
+  /// as measured while parsing it, be it with [`UpdateBundle::read_from`] or
+  /// through [`BinRead`](binrw::BinRead). `None` for bundles that were built
+  /// rather than parsed, and for unknown ones whose header end can't be told
+  pub fn header_len(&self) -> Option<u64> {
+    match self {
+      UpdateBundle::Signed(p) => p.header_len,
+      UpdateBundle::OtaV1Fc02(p) | UpdateBundle::OtaV1Fd03(p) => p.header_len,
+      UpdateBundle::OtaV2Fc04(p) | UpdateBundle::OtaV2Fd04(p) | UpdateBundle::OtaV2Fl01(p) => p.header_len,
+      UpdateBundle::RecoveryV1Fb01(p) | UpdateBundle::RecoveryV1Fb02(p) => p.header_len,
+      UpdateBundle::RecoveryV2Fb03(p) => p.header_len,
+      UpdateBundle::Unknown { .. } => None,
+    }
+  }
+
+  /// where the obfuscated payload sits in a bundle of `bundle_len` bytes,
+  /// relative to the start of the bundle; see [`UpdateBundle::header_len`]
+  /// for when it is unknown. empty if the bundle is truncated
+  pub fn payload_range(&self, bundle_len: u64) -> Option<Range<u64>> {
+    let header_len = self.header_len()?;
+    Some(header_len.min(bundle_len)..bundle_len)
+  }
+}
@@ -0,0 +1,257 @@
+use crate::{Error, SignatureEnvelope, UpdateBundle};
+use rsa::pkcs1::{DecodeRsaPrivateKey, DecodeRsaPublicKey};
+use rsa::pkcs8::{DecodePrivateKey, DecodePublicKey};
+use rsa::traits::PublicKeyParts;
+use rsa::{Pkcs1v15Sign, RsaPrivateKey, RsaPublicKey};
+use sha2::{Digest, Sha256};
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::io::{Read, Seek, SeekFrom, Write};
+
+// magic, cert_num and the reserved block preceding the signature
+pub(crate) const ENVELOPE_HEADER_SIZE: u64 = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureStatus {
+  Valid,
+  Invalid,
+  /// none of the supplied keys matches the size of the signature
+  UnknownKey,
+}
+
+impl Display for SignatureStatus {
+  fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+    match self {
+      SignatureStatus::Valid => write!(f, "valid"),
+      SignatureStatus::Invalid => write!(f, "invalid"),
+      SignatureStatus::UnknownKey => write!(f, "unknown key"),
+    }
+  }
+}
+
+/// a signature removed from an SP01 envelope
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnvelopeSignature {
+  pub cert_num: u32,
+  pub signature: Vec<u8>,
+}
+
+/// loads an rsa private key from a pkcs#1 or pkcs#8 pem document
+pub fn parse_private_key(pem: &str) -> Result<RsaPrivateKey, Error> {
+  RsaPrivateKey::from_pkcs1_pem(pem)
+    .or_else(|_| RsaPrivateKey::from_pkcs8_pem(pem))
+    .map_err(|e| Error::InvalidKey(e.to_string()))
+}
+
+/// loads an rsa public key from a pkcs#8 (spki) or pkcs#1 pem document
+pub fn parse_public_key(pem: &str) -> Result<RsaPublicKey, Error> {
+  RsaPublicKey::from_public_key_pem(pem)
+    .or_else(|_| RsaPublicKey::from_pkcs1_pem(pem))
+    .map_err(|e| Error::InvalidKey(e.to_string()))
+}
+
+fn sha256<R: Read>(reader: &mut R) -> std::io::Result<Vec<u8>> {
+  const BUFFER_SIZE: usize = 8192;
+
+  let mut hasher = Sha256::new();
+  let mut buffer = [0; BUFFER_SIZE];
+  loop {
+    let bytes_read = reader.read(&mut buffer)?;
+    if bytes_read == 0 {
+      break;
+    }
+    hasher.update(&buffer[..bytes_read]);
+  }
+
+  Ok(hasher.finalize().to_vec())
+}
+
+/// wraps the bundle read from `bundle` in an SP01 envelope carrying a
+/// sha256/pkcs#1 v1.5 signature over the bundle bytes
+pub fn sign_bundle<R: Read + Seek, W: Write>(
+  key: &RsaPrivateKey,
+  cert_num: u32,
+  bundle: &mut R,
+  writer: &mut W,
+) -> Result<(), Error> {
+  let signature = bundle_signature(key, cert_num, bundle)?;
+  write_signed_bundle(&signature, bundle, writer)
+}
+
+/// the signature [`sign_bundle`] would put on the bundle read from `bundle`,
+/// checking the key and the bundle before anything is written. `bundle` is
+/// left where it started
+pub fn bundle_signature<R: Read + Seek>(
+  key: &RsaPrivateKey,
+  cert_num: u32,
+  bundle: &mut R,
+) -> Result<EnvelopeSignature, Error> {
+  let sig_size = SignatureEnvelope::signature_size(cert_num);
+  if key.size() != sig_size {
+    return Err(Error::SignatureSizeMismatch {
+      cert_num,
+      expected: sig_size,
+      got: key.size(),
+    });
+  }
+
+  let bundle_start = bundle.stream_position()?;
+  let _bundle = UpdateBundle::read_supported(&mut *bundle)?;
+  bundle.seek(SeekFrom::Start(bundle_start))?;
+  let digest = sha256(bundle)?;
+  bundle.seek(SeekFrom::Start(bundle_start))?;
+
+  let signature = key.sign(Pkcs1v15Sign::new::<Sha256>(), &digest)?;
+  Ok(EnvelopeSignature { cert_num, signature })
+}
+
+/// writes an SP01 envelope carrying `signature`, followed by the bundle read
+/// from `bundle` unchanged
+pub fn write_signed_bundle<R: Read, W: Write>(
+  signature: &EnvelopeSignature,
+  bundle: &mut R,
+  writer: &mut W,
+) -> Result<(), Error> {
+  writer.write_all(b"SP01")?;
+  writer.write_all(&signature.cert_num.to_le_bytes())?;
+  writer.write_all(&[0; ENVELOPE_HEADER_SIZE as usize - 8])?;
+  writer.write_all(&signature.signature)?;
+  std::io::copy(bundle, writer)?;
+  Ok(())
+}
+
+/// checks the signature of the SP01 envelope read from `reader` against the
+/// wrapped bundle bytes, trying each of `keys` in turn
+pub fn verify_signature<R: Read + Seek>(
+  keys: &[RsaPublicKey],
+  reader: &mut R,
+) -> Result<SignatureStatus, Error> {
+  let bundle_start = reader.stream_position()?;
+  let bundle = UpdateBundle::read_from(reader)?;
+  let UpdateBundle::Signed(envelope) = bundle else {
+    return Err(Error::NotSigned(bundle.magic_str()));
+  };
+
+  let wrapped_start = bundle_start + envelope.envelope_len();
+  reader.seek(SeekFrom::Start(wrapped_start))?;
+  let digest = sha256(reader)?;
+
+  let mut status = SignatureStatus::UnknownKey;
+  for key in keys.iter().filter(|k| k.size() == envelope.signature.len()) {
+    if key.verify(Pkcs1v15Sign::new::<Sha256>(), &digest, &envelope.signature).is_ok() {
+      return Ok(SignatureStatus::Valid);
+    }
+    status = SignatureStatus::Invalid;
+  }
+
+  Ok(status)
+}
+
+/// strips the SP01 envelope read from `reader`, or every nested envelope when
+/// `all` is set, and copies the wrapped bundle bytes to `writer` unchanged.
+/// returns the removed signatures, outermost first
+pub fn unwrap_bundle<R: Read + Seek, W: Write>(
+  reader: &mut R,
+  writer: &mut W,
+  all: bool,
+) -> Result<Vec<EnvelopeSignature>, Error> {
+  let mut signatures = Vec::new();
+  loop {
+    let bundle_start = reader.stream_position()?;
+    let bundle = UpdateBundle::read_from(reader)?;
+    let UpdateBundle::Signed(envelope) = bundle else {
+      if signatures.is_empty() {
+        return Err(Error::NotSigned(bundle.magic_str()));
+      }
+      reader.seek(SeekFrom::Start(bundle_start))?;
+      break;
+    };
+
+    let wrapped_start = bundle_start + envelope.envelope_len();
+    reader.seek(SeekFrom::Start(wrapped_start))?;
+    signatures.push(EnvelopeSignature {
+      cert_num: envelope.cert_num,
+      signature: envelope.signature,
+    });
+    if !all {
+      break;
+    }
+  }
+
+  std::io::copy(reader, writer)?;
+  Ok(signatures)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{create_bundle, Device, OtaV1};
+  use rsa::rand_core::OsRng;
+  use std::io::Cursor;
+  use std::sync::OnceLock;
+
+  // key generation is slow in debug builds, so the keys are shared: two that
+  // fit cert 0 and a smaller one that fits no cert
+  fn keys() -> &'static [RsaPrivateKey; 3] {
+    static KEYS: OnceLock<[RsaPrivateKey; 3]> = OnceLock::new();
+    KEYS.get_or_init(|| {
+      let key = |bits| RsaPrivateKey::new(&mut OsRng, bits).unwrap();
+      [key(1024), key(1024), key(512)]
+    })
+  }
+
+  fn bundle() -> Vec<u8> {
+    let header = UpdateBundle::OtaV1Fc02(OtaV1::new(1, 2, Device::Kindle3WiFi, false).unwrap());
+    let mut bundle = Vec::new();
+    create_bundle(header, &mut Cursor::new(b"payload"), &mut bundle).unwrap();
+    bundle
+  }
+
+  fn signed(key: &RsaPrivateKey) -> Vec<u8> {
+    let mut signed = Vec::new();
+    sign_bundle(key, 0, &mut Cursor::new(bundle()), &mut signed).unwrap();
+    signed
+  }
+
+  fn verify(keys: &[&RsaPrivateKey], signed: &[u8]) -> SignatureStatus {
+    let keys: Vec<_> = keys.iter().map(|k| k.to_public_key()).collect();
+    verify_signature(&keys, &mut Cursor::new(signed)).unwrap()
+  }
+
+  #[test]
+  fn signed_bundles_verify() {
+    let [key, other, small] = keys();
+    let signed = signed(key);
+    assert_eq!(verify(&[key], &signed), SignatureStatus::Valid);
+    assert_eq!(verify(&[small, other, key], &signed), SignatureStatus::Valid);
+    assert_eq!(verify(&[other], &signed), SignatureStatus::Invalid);
+    assert_eq!(verify(&[small], &signed), SignatureStatus::UnknownKey);
+  }
+
+  #[test]
+  fn tampered_bundles_are_invalid() {
+    let [key, ..] = keys();
+    let mut signed = signed(key);
+    *signed.last_mut().unwrap() ^= 1;
+    assert_eq!(verify(&[key], &signed), SignatureStatus::Invalid);
+  }
+
+  #[test]
+  fn keys_of_the_wrong_size_write_nothing() {
+    let [key, ..] = keys();
+    let mut written = Vec::new();
+    let result = sign_bundle(key, 2, &mut Cursor::new(bundle()), &mut written);
+    assert!(matches!(result, Err(Error::SignatureSizeMismatch { cert_num: 2, expected: 256, got: 128 })));
+    assert!(written.is_empty());
+  }
+
+  #[test]
+  fn unwrapping_restores_the_bundle() {
+    let [key, ..] = keys();
+    let signed = signed(key);
+    let mut unwrapped = Vec::new();
+    let signatures = unwrap_bundle(&mut Cursor::new(&signed), &mut unwrapped, false).unwrap();
+    assert_eq!(unwrapped, bundle());
+    assert_eq!(signatures.len(), 1);
+    assert_eq!(signatures[0].signature, signed[64..64 + 128]);
+  }
+}
@@ -0,0 +1,240 @@
+use crate::{Device, Error, Platform};
+use std::num::ParseIntError;
+use std::str::FromStr;
+use strum::IntoEnumIterator;
+
+// a device model: its short aliases, the variant a bare alias stands for (if
+// any variant is known well enough) and the variant name prefixes of its group
+struct Model {
+  aliases: &'static [&'static str],
+  default: Option<Device>,
+  prefixes: &'static [&'static str],
+}
+
+const MODELS: &[Model] = &[
+  Model { aliases: &["k1"], default: Some(Device::Kindle1), prefixes: &["Kindle1"] },
+  Model { aliases: &["k2"], default: Some(Device::Kindle2US), prefixes: &["Kindle2"] },
+  Model { aliases: &["dx"], default: Some(Device::KindleDXUS), prefixes: &["KindleDXUS", "KindleDXInternational"] },
+  Model { aliases: &["dxg"], default: Some(Device::KindleDXGraphite), prefixes: &["KindleDXGraphite"] },
+  Model { aliases: &["k3"], default: Some(Device::Kindle3WiFi), prefixes: &["Kindle3"] },
+  Model { aliases: &["k4"], default: Some(Device::Kindle4NonTouch), prefixes: &["Kindle4"] },
+  Model { aliases: &["kt", "k5"], default: Some(Device::Kindle5TouchWiFi), prefixes: &["Kindle5Touch"] },
+  Model { aliases: &["kpw", "pw"], default: Some(Device::KindlePaperWhiteWiFi), prefixes: &["KindlePaperWhiteWiFi"] },
+  Model { aliases: &["kpw2", "pw2"], default: Some(Device::KindlePaperWhite2WiFi), prefixes: &["KindlePaperWhite2"] },
+  Model { aliases: &["kt2"], default: Some(Device::KindleBasic), prefixes: &["KindleBasic"] },
+  Model { aliases: &["kv"], default: Some(Device::KindleVoyageWiFi), prefixes: &["KindleVoyage"] },
+  Model {
+    aliases: &["kpw3", "pw3"],
+    default: Some(Device::KindlePaperWhite3WiFi),
+    prefixes: &["KindlePaperWhite3", "KindlePW3"],
+  },
+  Model { aliases: &["koa"], default: Some(Device::KindleOasisWiFi), prefixes: &["KindleOasis"] },
+  Model { aliases: &["kt3"], default: Some(Device::KindleBasic2), prefixes: &["KindleBasic2"] },
+  Model { aliases: &["koa2"], default: Some(Device::KindleOasis2WiFi8GB), prefixes: &["KindleOasis2"] },
+  Model {
+    aliases: &["kpw4", "pw4"],
+    default: Some(Device::KindlePaperWhite4WiFi8GB),
+    prefixes: &["KindlePaperWhite4", "KindlePW4"],
+  },
+  Model { aliases: &["kt4"], default: Some(Device::KindleBasic3), prefixes: &["KindleBasic3"] },
+  Model { aliases: &["koa3"], default: Some(Device::KindleOasis3WiFi8GB), prefixes: &["KindleOasis3"] },
+  Model { aliases: &["kpw5", "pw5"], default: Some(Device::KindlePaperWhite5), prefixes: &["KindlePaperWhite5"] },
+  Model { aliases: &["kt5"], default: Some(Device::KindleBasic4_2AQ), prefixes: &["KindleBasic4"] },
+  Model { aliases: &["ks"], default: Some(Device::KindleScribe_23L), prefixes: &["KindleScribe"] },
+  Model { aliases: &["kt6"], default: None, prefixes: &["KindleBasic5"] },
+  Model { aliases: &["kpw6", "pw6"], default: None, prefixes: &["KindlePaperWhite6"] },
+  Model { aliases: &["ks2"], default: None, prefixes: &["KindleScribe2"] },
+  Model { aliases: &["kcs"], default: None, prefixes: &["KindleColorSoft"] },
+];
+
+const PLATFORM_ALIASES: &[(&str, Platform)] = &[
+  ("unspecified", Platform::PlatUnspecified),
+  ("mario", Platform::MarioDeprecated),
+  ("yoshime-p", Platform::YoshimeProto),
+  ("yoshime3", Platform::Yoshime),
+];
+
+/// parses a numeric code written in hex (`0x...`) or decimal
+pub fn parse_code(s: &str) -> Result<u64, ParseIntError> {
+  match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+    Some(hex) => u64::from_str_radix(hex, 16),
+    None => s.parse(),
+  }
+}
+
+fn model(alias: &str) -> Option<&'static Model> {
+  MODELS.iter().find(|m| m.aliases.iter().any(|a| a.eq_ignore_ascii_case(alias)))
+}
+
+// the model a variant belongs to is the one with the longest prefix of its
+// name, so `KindleOasis2WiFi8GB` goes to `KindleOasis2` rather than `KindleOasis`
+fn model_of(device: Device) -> Option<&'static Model> {
+  let name = format!("{:?}", device);
+  MODELS
+    .iter()
+    .flat_map(|m| m.prefixes.iter().map(move |prefix| (prefix, m)))
+    .filter(|(prefix, _)| name.starts_with(**prefix))
+    .max_by_key(|(prefix, _)| prefix.len())
+    .map(|(_, m)| m)
+}
+
+impl FromStr for Device {
+  type Err = Error;
+
+  /// accepts a display name (`Kindle PaperWhite 5 (2021)`), a variant name
+  /// (`KindlePaperWhite5`), a model alias (`kpw5`) or a hex/decimal code,
+  /// ignoring case
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let s = s.trim();
+    let unknown = || Error::UnknownDevice(s.to_string());
+
+    if let Ok(code) = parse_code(s) {
+      return Device::from_repr(code as usize).filter(|&d| d != Device::Unknown).ok_or_else(unknown);
+    }
+    if let Some(model) = model(s) {
+      return model.default.ok_or_else(|| Error::AmbiguousDevice(s.to_string()));
+    }
+    Device::known()
+      .find(|d| d.to_string().eq_ignore_ascii_case(s) || format!("{:?}", d).eq_ignore_ascii_case(s))
+      .ok_or_else(unknown)
+  }
+}
+
+impl Device {
+  /// like [`Device::from_str`], but also expands `all-<alias>` (e.g. `all-pw5`)
+  /// to every variant of that model
+  pub fn parse_group(s: &str) -> Result<Vec<Device>, Error> {
+    let s = s.trim();
+    match s.get(..4) {
+      Some(prefix) if prefix.eq_ignore_ascii_case("all-") => {
+        let model = model(&s[4..]).ok_or_else(|| Error::UnknownDevice(s.to_string()))?;
+        Ok(Device::known().filter(|&d| model_of(d).is_some_and(|m| std::ptr::eq(m, model))).collect())
+      }
+      _ => s.parse().map(|d| vec![d]),
+    }
+  }
+}
+
+impl FromStr for Platform {
+  type Err = Error;
+
+  /// accepts a display name (`Yoshime (Yoshime3)`), a variant name (`Yoshime`),
+  /// an alias (`mario`, `yoshime3`) or a hex/decimal code, ignoring case
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let s = s.trim();
+    let unknown = || Error::UnknownPlatform(s.to_string());
+
+    if let Ok(code) = parse_code(s) {
+      return Platform::from_repr(code as usize).filter(|&p| p != Platform::Unknown).ok_or_else(unknown);
+    }
+    if let Some(&(_, platform)) = PLATFORM_ALIASES.iter().find(|(a, _)| a.eq_ignore_ascii_case(s)) {
+      return Ok(platform);
+    }
+    Platform::iter()
+      .filter(|&p| p != Platform::Unknown)
+      .find(|p| p.to_string().eq_ignore_ascii_case(s) || format!("{:?}", p).eq_ignore_ascii_case(s))
+      .ok_or_else(unknown)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn devices_parse_from_names_aliases_and_codes() {
+    let cases = [
+      ("Kindle PaperWhite 5 (2021)", Device::KindlePaperWhite5),
+      ("kindle paperwhite 5 (2021)", Device::KindlePaperWhite5),
+      ("KindlePaperWhite5", Device::KindlePaperWhite5),
+      ("kindlepaperwhite5", Device::KindlePaperWhite5),
+      ("kpw5", Device::KindlePaperWhite5),
+      ("PW5", Device::KindlePaperWhite5),
+      ("0x6FF", Device::KindlePaperWhite5),
+      ("0X6ff", Device::KindlePaperWhite5),
+      ("1791", Device::KindlePaperWhite5),
+      (" k3 ", Device::Kindle3WiFi),
+      ("kt", Device::Kindle5TouchWiFi),
+      ("k5", Device::Kindle5TouchWiFi),
+      ("kpw", Device::KindlePaperWhiteWiFi),
+      ("kv", Device::KindleVoyageWiFi),
+      ("koa", Device::KindleOasisWiFi),
+      ("koa2", Device::KindleOasis2WiFi8GB),
+      ("ks", Device::KindleScribe_23L),
+      ("Kindle Scribe (Variant 23L)", Device::KindleScribe_23L),
+    ];
+    for (input, expected) in cases {
+      assert_eq!(input.parse::<Device>().ok(), Some(expected), "{}", input);
+    }
+  }
+
+  #[test]
+  fn devices_that_dont_parse() {
+    for input in ["kcs", "KT6", "kpw6"] {
+      assert!(matches!(input.parse::<Device>(), Err(Error::AmbiguousDevice(_))), "{}", input);
+    }
+    for input in ["", "nope", "Unknown", "0xFFFF", "0x", "kpw5x", "all-kpw5"] {
+      assert!(matches!(input.parse::<Device>(), Err(Error::UnknownDevice(_))), "{}", input);
+    }
+  }
+
+  #[test]
+  fn groups_take_the_longest_prefix() {
+    let names = |alias: &str| -> Vec<String> {
+      let group = Device::parse_group(&format!("all-{}", alias)).unwrap();
+      group.iter().map(|d| format!("{:?}", d)).collect()
+    };
+
+    let oasis = names("koa");
+    assert_eq!(oasis.len(), 6);
+    assert!(oasis.iter().all(|n| n.starts_with("KindleOasis") && !n.starts_with("KindleOasis2") && !n.starts_with("KindleOasis3")));
+    assert!(names("koa2").iter().all(|n| n.starts_with("KindleOasis2")));
+    assert!(names("kpw4").iter().all(|n| n.starts_with("KindlePaperWhite4") || n.starts_with("KindlePW4")));
+    assert!(names("KPW").iter().all(|n| n.starts_with("KindlePaperWhiteWiFi")));
+    assert!(names("kt").iter().all(|n| n.starts_with("Kindle5Touch")));
+    assert!(names("kcs").contains(&"KindleColorSoftUnknown_3H7".to_string()));
+
+    assert_eq!(Device::parse_group("kpw5").unwrap(), [Device::KindlePaperWhite5]);
+    assert!(matches!(Device::parse_group("all-nope"), Err(Error::UnknownDevice(_))));
+  }
+
+  #[test]
+  fn groups_split_the_models() {
+    let mut seen = Vec::new();
+    for model in MODELS {
+      let group = Device::parse_group(&format!("all-{}", model.aliases[0])).unwrap();
+      assert!(!group.is_empty(), "{}", model.aliases[0]);
+      if let Some(default) = model.default {
+        assert!(group.contains(&default), "{}", model.aliases[0]);
+      }
+      for device in group {
+        assert!(!seen.contains(&device), "{:?} in two groups", device);
+        seen.push(device);
+      }
+    }
+  }
+
+  #[test]
+  fn platforms_parse_from_names_aliases_and_codes() {
+    let cases = [
+      ("Luigi", Platform::Luigi),
+      ("luigi", Platform::Luigi),
+      ("Yoshime (Yoshime3)", Platform::Yoshime),
+      ("Yoshime", Platform::Yoshime),
+      ("yoshime3", Platform::Yoshime),
+      ("yoshime-p", Platform::YoshimeProto),
+      ("mario", Platform::MarioDeprecated),
+      ("Mario (Deprecated)", Platform::MarioDeprecated),
+      ("unspecified", Platform::PlatUnspecified),
+      ("0x0E", Platform::Bellatrix4),
+      ("14", Platform::Bellatrix4),
+      (" bellatrix4 ", Platform::Bellatrix4),
+    ];
+    for (input, expected) in cases {
+      assert_eq!(input.parse::<Platform>().ok(), Some(expected), "{}", input);
+    }
+    for input in ["", "nope", "Unknown", "0xFF"] {
+      assert!(matches!(input.parse::<Platform>(), Err(Error::UnknownPlatform(_))), "{}", input);
+    }
+  }
+}
@@ -0,0 +1,76 @@
+use crate::archive::sniff;
+use crate::{
+  deobfuscate_stream, md5_hex, write_header, DeobfuscatingReader, Error, PayloadFormat, PayloadMd5, UpdateBundle,
+  RECOVERY_HEADER_SIZE,
+};
+use std::io::{Cursor, Write};
+
+/// a bundle parsed in place from a byte buffer such as a memory-mapped file.
+/// only the header bytes are read; the payload is borrowed untouched
+#[derive(Debug)]
+pub struct BundleView<'a> {
+  pub bundle: UpdateBundle,
+  /// the raw header, including any signature envelopes
+  pub header: &'a [u8],
+  /// the obfuscated payload following the innermost header
+  pub payload: &'a [u8],
+}
+
+impl UpdateBundle {
+  /// parses the bundle header at the start of `data` and splits off the
+  /// payload without copying it. unrecognized magics parse as
+  /// [`UpdateBundle::Unknown`], with the bytes after the header preview as payload
+  pub fn parse_bytes(data: &[u8]) -> Result<BundleView<'_>, Error> {
+    let mut cursor = Cursor::new(data);
+    let bundle = UpdateBundle::read_from(&mut cursor)?;
+    let (header, payload) = data.split_at(cursor.position() as usize);
+    Ok(BundleView { bundle, header, payload })
+  }
+}
+
+impl BundleView<'_> {
+  fn supported_payload(&self) -> Result<&[u8], Error> {
+    match self.bundle.innermost() {
+      UpdateBundle::Unknown { magic, .. } => Err(Error::UnknownMagic(*magic)),
+      _ => Ok(self.payload),
+    }
+  }
+
+  /// sniffs the payload format from the first kilobyte or so of the payload
+  pub fn payload_format(&self) -> Result<PayloadFormat, Error> {
+    Ok(sniff(self.supported_payload()?))
+  }
+
+  /// hashes the deobfuscated payload for comparison with the header md5
+  pub fn verify_payload_md5(&self) -> Result<PayloadMd5, Error> {
+    let actual = md5_hex(&mut DeobfuscatingReader::new(self.supported_payload()?))?;
+    Ok(PayloadMd5 {
+      expected: self.bundle.md5_hash().unwrap_or_default().to_string(),
+      actual,
+    })
+  }
+
+  /// writes the deobfuscated payload to `writer` in page-aligned chunks of
+  /// [`CHUNK_SIZE`](crate::CHUNK_SIZE), transformed on `jobs` threads;
+  /// `writer` is best left unbuffered
+  pub fn dump_payload<W: Write + Send>(&self, writer: &mut W, jobs: usize) -> Result<(), Error> {
+    deobfuscate_stream(&mut self.supported_payload()?, writer, jobs)?;
+    Ok(())
+  }
+
+  /// writes the bundle back followed by the untouched payload. header bytes
+  /// that `bundle` doesn't carry, such as the reserved rest of recovery
+  /// headers, are copied from `header`, so an unedited view is reproduced
+  /// byte for byte
+  pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+    let recovery_block = match self.bundle.innermost() {
+      UpdateBundle::RecoveryV1Fb01(_) | UpdateBundle::RecoveryV1Fb02(_) | UpdateBundle::RecoveryV2Fb03(_) => {
+        &self.header[self.header.len().saturating_sub(RECOVERY_HEADER_SIZE)..]
+      }
+      _ => &[],
+    };
+    write_header(&self.bundle, recovery_block, writer)?;
+    writer.write_all(self.payload)?;
+    Ok(())
+  }
+}